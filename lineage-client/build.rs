@@ -0,0 +1,12 @@
+fn main() {
+    // the client side of the service lineage-core serves, its messages are
+    // the hand-written ones of lineage_core::proto
+    tonic_build::configure()
+        .build_server(false)
+        .extern_path(".lineage", "::lineage_core::proto")
+        .compile(
+            &["../lineage-core/proto/lineage.proto"],
+            &["../lineage-core/proto"],
+        )
+        .unwrap();
+}
@@ -0,0 +1,321 @@
+//! A `Graph` backed by a remote engine, speaking the gRPC protocol of
+//! `lineage_core::server`. Code written against `Arc<dyn Graph<String>>`
+//! runs unchanged whether the graph lives in process, where
+//! `lineage_core::differential::new` upcasts to it, or behind a server.
+//!
+//! The protocol carries the requests of `lineage_core::proto::Request` and
+//! the two change streams, the counts are answered by combining them on the
+//! client. The wider `Lineage` is only offered in process.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crossbeam::channel::{unbounded, Receiver};
+use lineage_core::lineage::{ChangeBatch, Graph, Impact, Metadata, Timestamp};
+use lineage_core::proto::{
+    self, request, response, Edge, Empty, NameK, Request, Response, Upsert, UpsertBatch,
+};
+use tokio::runtime::Runtime;
+use tonic::transport::{Channel, Endpoint};
+
+mod pb {
+    tonic::include_proto!("lineage");
+}
+
+use pb::lineage_client::LineageClient;
+
+/// Connects to the server at `endpoint`, e.g. `http://127.0.0.1:50051`, the
+/// remote counterpart of `lineage_core::differential::new`.
+pub fn connect(endpoint: impl Into<String>) -> Result<Arc<dyn Graph<String>>, ConnectError> {
+    Ok(Arc::new(RemoteLineage::connect(endpoint)?))
+}
+
+/// Why `connect` failed.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The runtime the client blocks on could not be started.
+    Runtime(io::Error),
+    /// The endpoint is malformed or the server could not be reached.
+    Transport(tonic::transport::Error),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Runtime(err) => write!(f, "failed to start the client runtime: {}", err),
+            ConnectError::Transport(err) => write!(f, "failed to connect: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectError::Runtime(err) => Some(err),
+            ConnectError::Transport(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for ConnectError {
+    fn from(err: io::Error) -> Self {
+        ConnectError::Runtime(err)
+    }
+}
+
+impl From<tonic::transport::Error> for ConnectError {
+    fn from(err: tonic::transport::Error) -> Self {
+        ConnectError::Transport(err)
+    }
+}
+
+/// Every call blocks on a runtime of its own, so the methods must not be
+/// called from within another tokio runtime.
+///
+/// Like the in-process backend once its worker is gone, the `Graph` methods
+/// panic when the server can't be reached. `try_call` reports the failure
+/// instead.
+pub struct RemoteLineage {
+    runtime: Runtime,
+    client: LineageClient<Channel>,
+}
+
+impl RemoteLineage {
+    pub fn connect(endpoint: impl Into<String>) -> Result<Self, ConnectError> {
+        let runtime = Runtime::new()?;
+        let endpoint = Endpoint::from_shared(endpoint.into())?;
+        let channel = runtime.block_on(endpoint.connect())?;
+        Ok(RemoteLineage {
+            runtime,
+            client: LineageClient::new(channel),
+        })
+    }
+
+    /// Sends `request` as is, returning the status of a failed call rather
+    /// than panicking.
+    pub fn try_call(&self, request: Request) -> Result<Response, tonic::Status> {
+        let mut client = self.client.clone();
+        let response = self.runtime.block_on(client.call(request))?;
+        Ok(response.into_inner())
+    }
+
+    fn call(&self, kind: request::Kind) -> response::Kind {
+        let request = Request { kind: Some(kind) };
+        match self.try_call(request) {
+            Ok(response) => response.kind.expect("response without a kind"),
+            Err(status) => panic!("lineage call failed: {}", status),
+        }
+    }
+
+    fn update(&self, kind: request::Kind) {
+        match self.call(kind) {
+            response::Kind::Done(_) => {}
+            other => unexpected(other),
+        }
+    }
+
+    fn names(&self, kind: request::Kind) -> Vec<String> {
+        match self.call(kind) {
+            response::Kind::Names(list) => list.names,
+            other => unexpected(other),
+        }
+    }
+
+    fn cascade(&self, kind: request::Kind) -> HashMap<String, Vec<String>> {
+        match self.call(kind) {
+            response::Kind::Cascade(cascade) => entries(cascade),
+            other => unexpected(other),
+        }
+    }
+
+    fn both_cascades(&self, kind: request::Kind) -> Impact<String> {
+        match self.call(kind) {
+            response::Kind::Impact(impact) => Impact {
+                upstream: impact.upstream.map(entries).unwrap_or_default(),
+                downstream: impact.downstream.map(entries).unwrap_or_default(),
+            },
+            other => unexpected(other),
+        }
+    }
+}
+
+fn entries(cascade: proto::Cascade) -> HashMap<String, Vec<String>> {
+    cascade
+        .entries
+        .into_iter()
+        .map(|(name, list)| (name, list.names))
+        .collect()
+}
+
+fn wire_name(name: String) -> proto::Name {
+    proto::Name { name }
+}
+
+fn name_k(name: String, k: usize) -> NameK {
+    NameK { name, k: k as u64 }
+}
+
+fn unexpected(kind: response::Kind) -> ! {
+    panic!("unexpected response {:?}", kind)
+}
+
+impl Graph<String> for RemoteLineage {
+    fn exists(&self, name: String) -> bool {
+        match self.call(request::Kind::Exists(wire_name(name))) {
+            response::Kind::Exists(exists) => exists,
+            other => unexpected(other),
+        }
+    }
+
+    fn nodes(&self) -> Vec<String> {
+        self.names(request::Kind::Nodes(Empty {}))
+    }
+
+    fn edges(&self) -> Vec<(String, String)> {
+        match self.call(request::Kind::Edges(Empty {})) {
+            response::Kind::Edges(list) => list
+                .edges
+                .into_iter()
+                .map(|edge| (edge.name, edge.dependency))
+                .collect(),
+            other => unexpected(other),
+        }
+    }
+
+    fn roots(&self) -> Vec<String> {
+        self.names(request::Kind::Roots(Empty {}))
+    }
+
+    fn leaves(&self) -> Vec<String> {
+        self.names(request::Kind::Leaves(Empty {}))
+    }
+
+    fn orphans(&self) -> Vec<String> {
+        self.names(request::Kind::Orphans(Empty {}))
+    }
+
+    fn dependencies(&self, name: String) -> Vec<String> {
+        self.names(request::Kind::Dependencies(wire_name(name)))
+    }
+
+    fn dependents(&self, name: String) -> Vec<String> {
+        self.names(request::Kind::Dependents(wire_name(name)))
+    }
+
+    fn dependencies_cascade(&self, name: String) -> HashMap<String, Vec<String>> {
+        self.cascade(request::Kind::DependenciesCascade(wire_name(name)))
+    }
+
+    fn dependents_cascade(&self, name: String) -> HashMap<String, Vec<String>> {
+        self.cascade(request::Kind::DependentsCascade(wire_name(name)))
+    }
+
+    fn dependencies_count(&self, name: String) -> usize {
+        self.dependencies(name).len()
+    }
+
+    fn dependents_count(&self, name: String) -> usize {
+        self.dependents(name).len()
+    }
+
+    fn impact(&self, name: String) -> Impact<String> {
+        self.both_cascades(request::Kind::Impact(wire_name(name)))
+    }
+
+    fn dependencies_k(&self, name: String, k: usize) -> HashMap<String, Vec<String>> {
+        self.cascade(request::Kind::DependenciesK(name_k(name, k)))
+    }
+
+    fn dependents_k(&self, name: String, k: usize) -> HashMap<String, Vec<String>> {
+        self.cascade(request::Kind::DependentsK(name_k(name, k)))
+    }
+
+    fn neighborhood(&self, name: String, k: usize) -> Impact<String> {
+        self.both_cascades(request::Kind::Neighborhood(name_k(name, k)))
+    }
+
+    /// The channel closes when the server ends the stream or the connection
+    /// fails.
+    fn subscribe_dependents_cascade(&self, name: String) -> Receiver<ChangeBatch<String>> {
+        let (tx, rx) = unbounded();
+        let mut client = self.client.clone();
+        self.runtime.spawn(async move {
+            let mut stream = match client.subscribe_dependents_cascade(wire_name(name)).await {
+                Ok(response) => response.into_inner(),
+                Err(_) => return,
+            };
+            while let Ok(Some(batch)) = stream.message().await {
+                let batch = batch
+                    .changes
+                    .into_iter()
+                    .map(|c| ((c.name, c.dependency), c.time, c.diff as isize))
+                    .collect();
+                if tx.send(batch).is_err() {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    /// The channel closes when the server ends the stream or the connection
+    /// fails.
+    fn changes(&self) -> Receiver<(String, String, isize, Timestamp)> {
+        let (tx, rx) = unbounded();
+        let mut client = self.client.clone();
+        self.runtime.spawn(async move {
+            let mut stream = match client.changes(Empty {}).await {
+                Ok(response) => response.into_inner(),
+                Err(_) => return,
+            };
+            while let Ok(Some(c)) = stream.message().await {
+                if tx
+                    .send((c.name, c.dependency, c.diff as isize, c.time))
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        rx
+    }
+
+    fn get_metadata(&self, name: String) -> Option<Metadata> {
+        match self.call(request::Kind::GetMetadata(wire_name(name))) {
+            response::Kind::Metadata(m) if m.found => Some(m.metadata),
+            response::Kind::Metadata(_) => None,
+            other => unexpected(other),
+        }
+    }
+
+    fn upsert(&self, name: String, dependencies: Vec<String>) {
+        self.update(request::Kind::Upsert(Upsert { name, dependencies }));
+    }
+
+    fn upsert_batch(&self, batch: Vec<(String, Vec<String>)>) {
+        let upserts = batch
+            .into_iter()
+            .map(|(name, dependencies)| Upsert { name, dependencies })
+            .collect();
+        self.update(request::Kind::UpsertBatch(UpsertBatch { upserts }));
+    }
+
+    fn add_dependency(&self, name: String, dependency: String) {
+        self.update(request::Kind::AddDependency(Edge { name, dependency }));
+    }
+
+    fn remove_dependency(&self, name: String, dependency: String) {
+        self.update(request::Kind::RemoveDependency(Edge { name, dependency }));
+    }
+
+    fn delete(&self, name: String) {
+        self.update(request::Kind::Delete(wire_name(name)));
+    }
+
+    fn set_metadata(&self, name: String, metadata: Metadata) {
+        let req = proto::SetMetadata { name, metadata };
+        self.update(request::Kind::SetMetadata(req));
+    }
+}
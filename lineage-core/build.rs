@@ -0,0 +1,10 @@
+fn main() {
+    // the gRPC service of proto/lineage.proto, its messages are the
+    // hand-written ones of src/proto.rs
+    #[cfg(feature = "server")]
+    tonic_build::configure()
+        .build_client(false)
+        .extern_path(".lineage", "crate::proto")
+        .compile(&["proto/lineage.proto"], &["proto"])
+        .unwrap();
+}
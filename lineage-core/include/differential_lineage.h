@@ -0,0 +1,35 @@
+/* C API of the lineage engine, built with the ffi feature. Requests and
+ * responses are the protobuf messages `lineage.Request` and
+ * `lineage.Response` of proto/lineage.proto. */
+#ifndef DIFFERENTIAL_LINEAGE_H
+#define DIFFERENTIAL_LINEAGE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct LineageHandle LineageHandle;
+
+/* A new engine, to be released with lineage_free. */
+LineageHandle *lineage_new(void);
+
+/* Releases an engine, NULL is ignored. */
+void lineage_free(LineageHandle *handle);
+
+/* Answers an encoded Request with an encoded Response of *response_len
+ * bytes, to be released with lineage_buffer_free. NULL when the request
+ * doesn't decode or the engine failed. */
+uint8_t *lineage_call(const LineageHandle *handle, const uint8_t *request, size_t request_len,
+                      size_t *response_len);
+
+/* Releases a response of lineage_call, NULL is ignored. */
+void lineage_buffer_free(uint8_t *buffer, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
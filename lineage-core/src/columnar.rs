@@ -0,0 +1,117 @@
+//! Columnar output of query results and of the edge set, for analytics
+//! tools that read Arrow or Parquet.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::Write;
+#[cfg(feature = "parquet")]
+use std::path::Path;
+use std::sync::Arc;
+
+use arrow::array::{StringBuilder, UInt64Builder};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "parquet")]
+use parquet::arrow::ArrowWriter;
+#[cfg(feature = "parquet")]
+use parquet::errors::ParquetError;
+
+use crate::lineage::Timestamp;
+
+/// Columns of a cascade: each node, one of its dependencies and the node's
+/// hop distance from the queried name. Nodes without dependencies get a
+/// single row with a null dependency.
+pub fn cascade_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("dependency", DataType::Utf8, true),
+        Field::new("depth", DataType::UInt64, false),
+    ]))
+}
+
+/// The result of `dependencies_cascade_with_depth` or
+/// `dependents_cascade_with_depth` as one record batch of `cascade_schema`,
+/// ordered by depth and name.
+pub fn cascade_batch<N: Display>(
+    cascade: &HashMap<N, (usize, Vec<N>)>,
+) -> Result<RecordBatch, ArrowError> {
+    let mut rows: Vec<(usize, String, Option<String>)> = vec![];
+    for (name, (depth, dependencies)) in cascade {
+        if dependencies.is_empty() {
+            rows.push((*depth, name.to_string(), None));
+        }
+        for dependency in dependencies {
+            rows.push((*depth, name.to_string(), Some(dependency.to_string())));
+        }
+    }
+    rows.sort();
+
+    let mut names = StringBuilder::new();
+    let mut dependencies = StringBuilder::new();
+    let mut depths = UInt64Builder::new();
+    for (depth, name, dependency) in rows {
+        names.append_value(name);
+        dependencies.append_option(dependency);
+        depths.append_value(depth as u64);
+    }
+    RecordBatch::try_new(
+        cascade_schema(),
+        vec![
+            Arc::new(names.finish()),
+            Arc::new(dependencies.finish()),
+            Arc::new(depths.finish()),
+        ],
+    )
+}
+
+/// Writes `batch` to `writer` in the Arrow IPC streaming format.
+pub fn write_ipc<W: Write>(writer: W, batch: &RecordBatch) -> Result<(), ArrowError> {
+    let mut writer = StreamWriter::try_new(writer, &batch.schema())?;
+    writer.write(batch)?;
+    writer.finish()
+}
+
+/// Columns of the edge set: each `(name, dependency)` edge and when the
+/// dependencies of `name` last changed, null when older than the retained
+/// history. Exports can then be merged by keeping newer rows only.
+pub fn edges_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("name", DataType::Utf8, false),
+        Field::new("dependency", DataType::Utf8, false),
+        Field::new("last_modified", DataType::UInt64, true),
+    ]))
+}
+
+/// `edges` as one record batch of `edges_schema`, in the given order.
+pub fn edges_batch(
+    edges: Vec<(String, String, Option<Timestamp>)>,
+) -> Result<RecordBatch, ArrowError> {
+    let mut names = StringBuilder::new();
+    let mut dependencies = StringBuilder::new();
+    let mut times = UInt64Builder::new();
+    for (name, dependency, last_modified) in edges {
+        names.append_value(name);
+        dependencies.append_value(dependency);
+        times.append_option(last_modified);
+    }
+    RecordBatch::try_new(
+        edges_schema(),
+        vec![
+            Arc::new(names.finish()),
+            Arc::new(dependencies.finish()),
+            Arc::new(times.finish()),
+        ],
+    )
+}
+
+/// Writes `batch` to a new Parquet file at `path`.
+#[cfg(feature = "parquet")]
+pub fn write_parquet(path: &Path, batch: &RecordBatch) -> Result<(), ParquetError> {
+    let file = std::fs::File::create(path)?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+    writer.write(batch)?;
+    writer.close()?;
+    Ok(())
+}
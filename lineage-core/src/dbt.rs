@@ -0,0 +1,82 @@
+//! Reading the dependency graph out of a dbt `manifest.json`, see
+//! `Lineage::import_dbt_manifest`.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::lineage::{Metadata, NodeType};
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    nodes: HashMap<String, ManifestNode>,
+    #[serde(default)]
+    sources: HashMap<String, ManifestNode>,
+}
+
+#[derive(Deserialize)]
+struct ManifestNode {
+    resource_type: String,
+    #[serde(default)]
+    depends_on: DependsOn,
+    #[serde(default)]
+    config: NodeConfig,
+    #[serde(default)]
+    tags: Vec<String>,
+    database: Option<String>,
+    schema: Option<String>,
+    package_name: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct DependsOn {
+    #[serde(default)]
+    nodes: Vec<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct NodeConfig {
+    materialized: Option<String>,
+}
+
+/// A node of the manifest with its dependencies, resource type and
+/// metadata.
+pub(crate) type DbtNode = (String, Vec<String>, NodeType, Metadata);
+
+/// Every model, seed, snapshot and source of the manifest at `path`, by
+/// `unique_id`. Tests are left out, they would otherwise show up as
+/// dependents of everything they check.
+pub(crate) fn read_manifest(path: &Path) -> io::Result<Vec<DbtNode>> {
+    let file = io::BufReader::new(std::fs::File::open(path)?);
+    let manifest: Manifest = serde_json::from_reader(file)?;
+    let nodes = manifest
+        .nodes
+        .into_iter()
+        .chain(manifest.sources)
+        .filter(|(_, node)| node.resource_type != "test")
+        .map(|(id, node)| {
+            let mut metadata = Metadata::new();
+            if let Some(materialized) = node.config.materialized {
+                metadata.insert("materialized".to_string(), materialized);
+            }
+            if !node.tags.is_empty() {
+                metadata.insert("tags".to_string(), node.tags.join(","));
+            }
+            let fields = [
+                ("database", node.database),
+                ("schema", node.schema),
+                ("package_name", node.package_name),
+            ];
+            for (key, value) in fields {
+                if let Some(value) = value {
+                    metadata.insert(key.to_string(), value);
+                }
+            }
+            (id, node.depends_on.nodes, node.resource_type, metadata)
+        })
+        .collect();
+    Ok(nodes)
+}
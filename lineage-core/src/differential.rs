@@ -0,0 +1,6503 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crossbeam::channel::{bounded, unbounded, Receiver, RecvTimeoutError, Sender};
+use differential_dataflow::operators::arrange::{
+    upsert, Arrange, ArrangeByKey, Arranged, TraceAgent,
+};
+use differential_dataflow::operators::{
+    Consolidate, Count, Iterate, Join, JoinCore, Reduce, Threshold,
+};
+use differential_dataflow::trace::implementations::ord::OrdValSpine;
+use differential_dataflow::trace::{BatchReader, Cursor, TraceReader};
+use differential_dataflow::{AsCollection, Collection, ExchangeData};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use timely::communication::Allocate;
+use timely::dataflow::channels::pact::Pipeline;
+use timely::dataflow::operators::input::Handle;
+use timely::dataflow::operators::Map;
+use timely::dataflow::operators::Probe;
+use timely::dataflow::operators::{Broadcast, Input, Inspect, ToStream};
+use timely::dataflow::{InputHandle, ProbeHandle, Scope, Stream};
+use timely::progress::frontier::AntichainRef;
+use timely::worker::{AsWorker, Worker};
+
+use crate::export;
+use crate::lineage::{
+    Attribution, AuditEntry, Bounded, ChangeBatch, CompactionStats, Condensation, EdgeDiff,
+    EdgeKind, Error, Graph, Impact, Lineage, Metadata, NodeType, QueryHandle, QuerySpec, Reply,
+    Stats, Stop, Timestamp, ValidTime, Wake, Weight,
+};
+use shared::Shared;
+use storage::Storage;
+
+mod records;
+mod shared;
+pub mod storage;
+#[cfg(test)]
+mod tests;
+
+/// Types usable as node names in the differential backend. Serde writes them
+/// to the write-ahead log, snapshots and backups.
+pub trait Key: ExchangeData + Hash + Serialize + DeserializeOwned {}
+
+impl<T: ExchangeData + Hash + Serialize + DeserializeOwned> Key for T {}
+
+/// Like `ToStream`, introducing the data on the first worker only. Every
+/// worker builds the same query dataflows, which would otherwise see the
+/// data once per worker.
+trait ToLeaderStream<D: timely::Data> {
+    fn to_leader_stream<G: Scope>(self, scope: &mut G) -> Stream<G, D>;
+}
+
+impl<I> ToLeaderStream<I::Item> for I
+where
+    I: IntoIterator + 'static,
+    I::IntoIter: 'static,
+    I::Item: timely::Data,
+{
+    fn to_leader_stream<G: Scope>(self, scope: &mut G) -> Stream<G, I::Item> {
+        let leader = scope.index() == 0;
+        self.into_iter().filter(move |_| leader).to_stream(scope)
+    }
+}
+
+struct Differential<N> {
+    tx: Sender<Envelope<N>>,
+    _thread: Option<JoinHandle<()>>,
+    inline: Option<Stepper<N>>,
+}
+
+impl<N: Key> Differential<N> {
+    fn send(&self, req: Message<N>) {
+        self.send_envelope(Envelope::new(req));
+    }
+
+    /// Sends the message `request` builds around the reply sender, returning
+    /// without waiting for the reply.
+    fn send_async<T>(&self, request: impl FnOnce(Sender<T>) -> Message<N>) -> Reply<T> {
+        let (tx, rx) = bounded(1);
+        let (reply, wake) = Reply::pending(rx);
+        let mut envelope = Envelope::new(request(tx));
+        envelope.wake = Some(wake);
+        self.send_envelope(envelope);
+        reply
+    }
+
+    fn send_envelope(&self, envelope: Envelope<N>) {
+        self.tx.send(envelope).unwrap();
+        if let Some(stepper) = &self.inline {
+            stepper.step();
+        }
+    }
+}
+
+/// Construction options for the differential backend.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Reject upserts whose dependencies would close a cycle. Every upsert,
+    /// or batch of them, then costs a reachability query.
+    pub reject_cycles: bool,
+    /// What `delete` does about nodes still depending on the deleted one.
+    pub dangling: DanglingPolicy,
+    /// Maintain the transitive closure of the graph as a standing
+    /// arrangement, turning cascades into lookups at the cost of keeping
+    /// every reachable pair in memory and updating it on every change.
+    pub maintain_closure: bool,
+    /// How much history compaction keeps behind the current time, bounding
+    /// how far back time-travel queries can look.
+    pub retention: Retention,
+    /// How many of the latest mutations `rollback` can undo. The default of
+    /// 0 keeps no journal.
+    pub journal: usize,
+    /// Append every dependency change to this file before applying it, and
+    /// replay the file on start. Saving a snapshot rewrites the file to hold
+    /// just the saved nodes, until then it grows.
+    pub wal: Option<PathBuf>,
+    /// Worker threads the dataflow runs on in this process, `0` meaning a
+    /// single one. Each holds a shard of every trace, so cascades over big
+    /// graphs spread across cores. Ignored when `cluster` is set.
+    pub workers: usize,
+    /// Run the dataflow on the workers of a timely cluster instead of a
+    /// single one, see `Cluster`.
+    pub cluster: Option<Cluster>,
+}
+
+impl Config {
+    /// Workers in each process.
+    fn threads(&self) -> usize {
+        self.cluster
+            .as_ref()
+            .map_or(self.workers, |c| c.threads)
+            .max(1)
+    }
+
+    fn processes(&self) -> usize {
+        self.cluster
+            .as_ref()
+            .map_or(1, |c| c.addresses.len().max(1))
+    }
+
+    fn timely(&self) -> timely::Config {
+        match &self.cluster {
+            Some(cluster) if cluster.addresses.len() > 1 => timely::Config {
+                communication: timely::CommunicationConfig::Cluster {
+                    threads: self.threads(),
+                    process: cluster.process,
+                    addresses: cluster.addresses.clone(),
+                    report: false,
+                    log_fn: Box::new(|_| None),
+                },
+                worker: timely::WorkerConfig::default(),
+            },
+            _ if self.threads() > 1 => timely::Config::process(self.threads()),
+            _ => timely::Config::thread(),
+        }
+    }
+}
+
+/// The processes and threads the workers run on.
+///
+/// Every worker builds the dataflows of every call, so every process of a
+/// cluster has to make the same calls in the same order, and each gets the
+/// answers on its own. The first worker alone feeds the inputs, appends to
+/// the write-ahead log and writes through to storage. TTLs go by the clock
+/// of each process and only work with a single one.
+#[derive(Clone, Debug, Default)]
+pub struct Cluster {
+    /// Workers in each process.
+    pub threads: usize,
+    /// Index of this process in `addresses`.
+    pub process: usize,
+    /// The `host:port` of every process, this one included. With fewer than
+    /// two the workers all run in this process.
+    pub addresses: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DanglingPolicy {
+    /// Leave the edges of dependents pointing at the deleted node.
+    #[default]
+    KeepDangling,
+    /// Remove the deleted node from the dependency list of every dependent.
+    PruneEdges,
+    /// Refuse to delete a node that still has dependents.
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Retention {
+    /// Keep the last `n` ticks of logical time.
+    Versions(Timestamp),
+    /// Keep the ticks reached within the given wall-clock duration.
+    Duration(Duration),
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Retention::Versions(0)
+    }
+}
+
+pub fn new<N: Key>() -> Arc<dyn Lineage<N>> {
+    with_config(Config::default())
+}
+
+pub fn with_config<N: Key>(config: Config) -> Arc<dyn Lineage<N>> {
+    let (tx, rx) = unbounded();
+    let thread = std::thread::spawn(move || run(rx, config, None));
+    Arc::new(Differential {
+        tx,
+        _thread: Some(thread),
+        inline: None,
+    })
+}
+
+/// Like `with_config`, writing every change through to `storage` and
+/// starting from the nodes stored there.
+pub fn with_storage<N: Key>(config: Config, storage: Box<dyn Storage<N>>) -> Arc<dyn Lineage<N>> {
+    let (tx, rx) = unbounded();
+    let thread = std::thread::spawn(move || run(rx, config, Some(storage)));
+    Arc::new(Differential {
+        tx,
+        _thread: Some(thread),
+        inline: None,
+    })
+}
+
+/// Like `new`, starting from the nodes saved by `Lineage::save_snapshot`.
+pub fn new_from_snapshot<N: Key>(path: impl AsRef<Path>) -> io::Result<Arc<dyn Lineage<N>>> {
+    with_config_from_snapshot(Config::default(), path)
+}
+
+/// Like `with_config`, starting from a snapshot. A file that is not a
+/// complete snapshot is `InvalidData`.
+pub fn with_config_from_snapshot<N: Key>(
+    config: Config,
+    path: impl AsRef<Path>,
+) -> io::Result<Arc<dyn Lineage<N>>> {
+    let bytes = std::fs::read(path)?;
+    let batch = records::decode_all(&bytes)?;
+    let (tx, rx) = unbounded();
+    tx.send(Envelope::new(Message::UpsertBatch { batch }))
+        .unwrap();
+    let thread = std::thread::spawn(move || run(rx, config, None));
+    Ok(Arc::new(Differential {
+        tx,
+        _thread: Some(thread),
+        inline: None,
+    }))
+}
+
+/// A backend whose worker runs on the calling thread instead of a thread of
+/// its own, e.g. on wasm32 where there are no threads.
+///
+/// Every call steps the worker until the call is answered, `step` does the
+/// same for subscriptions waiting on their updates. The backend can only be
+/// used from the thread that created it, so it is neither `Send` nor `Sync`.
+/// TTLs and `Retention::Duration` read
+/// the clock, which panics on `wasm32-unknown-unknown`.
+pub struct Inline<N: Key> {
+    lineage: Differential<N>,
+    _thread: PhantomData<Rc<()>>,
+}
+
+pub fn inline<N: Key>(config: Config) -> Inline<N> {
+    let (tx, rx) = unbounded();
+    let mut worker = Worker::new(
+        timely::WorkerConfig::default(),
+        timely::communication::allocator::Thread::new(),
+    );
+    let (ctx, traces) = build(&mut worker, config, None);
+    let id = NEXT_INLINE.with(|next| next.replace(next.get() + 1));
+    let state = InlineState {
+        worker,
+        ctx,
+        traces,
+    };
+    INLINE.with(|states| states.borrow_mut().insert(id, Box::new(state)));
+    Inline {
+        lineage: Differential {
+            tx,
+            _thread: None,
+            inline: Some(Stepper { rx, id }),
+        },
+        _thread: PhantomData,
+    }
+}
+
+impl<N: Key> Inline<N> {
+    /// Handles the pending calls and advances the dataflow.
+    pub fn step(&self) {
+        if let Some(stepper) = &self.lineage.inline {
+            stepper.step();
+        }
+    }
+}
+
+impl<N: Key> std::ops::Deref for Inline<N> {
+    type Target = dyn Lineage<N>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.lineage
+    }
+}
+
+impl<N: Key> Drop for Inline<N> {
+    fn drop(&mut self) {
+        if let Some(stepper) = &self.lineage.inline {
+            // the worker may be gone already if the thread is shutting down
+            let _ = INLINE.try_with(|states| states.borrow_mut().remove(&stepper.id));
+        }
+    }
+}
+
+thread_local! {
+    // the workers of the inline backends created on this thread, which keep
+    // their `Rc`s here so that `Differential` stays `Send + Sync`
+    static INLINE: RefCell<HashMap<usize, Box<dyn std::any::Any>>> = RefCell::new(HashMap::new());
+    static NEXT_INLINE: Cell<usize> = Cell::new(0);
+}
+
+struct Stepper<N> {
+    rx: Receiver<Envelope<N>>,
+    id: usize,
+}
+
+impl<N: Key> Stepper<N> {
+    fn step(&self) {
+        INLINE.with(|states| {
+            let mut states = states.borrow_mut();
+            let state = states
+                .get_mut(&self.id)
+                .and_then(|state| state.downcast_mut::<InlineState<N>>())
+                .expect("inline lineage used off the thread that created it");
+            state.step(&self.rx);
+        })
+    }
+}
+
+struct InlineState<N: Key> {
+    worker: Worker<timely::communication::allocator::Thread>,
+    ctx: Context<N>,
+    traces: Traces<N>,
+}
+
+impl<N: Key> InlineState<N> {
+    fn step(&mut self, rx: &Receiver<Envelope<N>>) {
+        let InlineState {
+            worker,
+            ctx,
+            traces,
+        } = self;
+        ctx.expire(traces, worker);
+        handle_batch(ctx, traces, rx.try_iter().collect(), worker);
+        if ctx.subscriptions.get() > 0 {
+            ctx.advance(worker);
+        } else {
+            worker.step();
+        }
+        let abandoned = ctx.abandoned.take();
+        ctx.release(abandoned, worker);
+    }
+}
+
+impl<N: Key> Graph<N> for Differential<N> {
+    fn exists(&self, name: N) -> bool {
+        let (tx, rx) = bounded(1);
+        let req = Message::Exists { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn nodes(&self) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Nodes { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn edges(&self) -> Vec<(N, N)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Edges { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn roots(&self) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Roots { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn leaves(&self) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Leaves { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn orphans(&self) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Orphans { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies(&self, name: N) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Dependencies { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents(&self, name: N) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Dependents { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade(&self, name: N) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascade { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade(&self, name: N) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascade { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_count(&self, name: N) -> usize {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCount {
+            name,
+            cascade: false,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_count(&self, name: N) -> usize {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCount {
+            name,
+            cascade: false,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn impact(&self, name: N) -> Impact<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Impact { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_k(&self, name: N, k: usize) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesK { name, k, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_k(&self, name: N, k: usize) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsK { name, k, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn neighborhood(&self, name: N, k: usize) -> Impact<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Neighborhood { name, k, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn subscribe_dependents_cascade(&self, name: N) -> Receiver<ChangeBatch<N>> {
+        let (tx, rx) = unbounded();
+        let req = Message::SubscribeDependentsCascade { name, tx };
+        self.send(req);
+        rx
+    }
+
+    fn changes(&self) -> Receiver<(N, N, isize, Timestamp)> {
+        let (tx, rx) = unbounded();
+        let req = Message::Changes { tx };
+        self.send(req);
+        rx
+    }
+
+    fn get_metadata(&self, name: N) -> Option<Metadata> {
+        let (tx, rx) = bounded(1);
+        let req = Message::GetMetadata { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn exists_async(&self, name: N) -> Reply<bool> {
+        self.send_async(|tx| Message::Exists { name, tx })
+    }
+
+    fn dependencies_async(&self, name: N) -> Reply<Vec<N>> {
+        self.send_async(|tx| Message::Dependencies { name, tx })
+    }
+
+    fn dependents_async(&self, name: N) -> Reply<Vec<N>> {
+        self.send_async(|tx| Message::Dependents { name, tx })
+    }
+
+    fn dependencies_cascade_async(&self, name: N) -> Reply<HashMap<N, Vec<N>>> {
+        self.send_async(|tx| Message::DependenciesCascade { name, tx })
+    }
+
+    fn dependents_cascade_async(&self, name: N) -> Reply<HashMap<N, Vec<N>>> {
+        self.send_async(|tx| Message::DependentsCascade { name, tx })
+    }
+
+    fn dependencies_k_async(&self, name: N, k: usize) -> Reply<HashMap<N, Vec<N>>> {
+        self.send_async(|tx| Message::DependenciesK { name, k, tx })
+    }
+
+    fn dependents_k_async(&self, name: N, k: usize) -> Reply<HashMap<N, Vec<N>>> {
+        self.send_async(|tx| Message::DependentsK { name, k, tx })
+    }
+
+    fn upsert(&self, name: N, dependencies: Vec<N>) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|d| (d, EdgeKind::new(), 1, ALWAYS))
+            .collect();
+        let req = Message::Upsert { name, dependencies };
+        self.send(req);
+    }
+
+    fn upsert_batch(&self, batch: Vec<(N, Vec<N>)>) {
+        let batch = batch
+            .into_iter()
+            .map(|(name, dependencies)| {
+                let dependencies = dependencies
+                    .into_iter()
+                    .map(|d| (d, EdgeKind::new(), 1, ALWAYS))
+                    .collect();
+                (name, dependencies)
+            })
+            .collect();
+        let req = Message::UpsertBatch { batch };
+        self.send(req);
+    }
+
+    fn add_dependency(&self, name: N, dependency: N) {
+        let req = Message::AddDependency { name, dependency };
+        self.send(req);
+    }
+
+    fn remove_dependency(&self, name: N, dependency: N) {
+        let req = Message::RemoveDependency { name, dependency };
+        self.send(req);
+    }
+
+    fn delete(&self, name: N) {
+        let req = Message::Delete { name };
+        self.send(req);
+    }
+
+    fn set_metadata(&self, name: N, metadata: Metadata) {
+        let req = Message::SetMetadata { name, metadata };
+        self.send(req);
+    }
+}
+
+impl<N: Key> Lineage<N> for Differential<N> {
+    fn topological_order(&self) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::TopologicalOrder { name: None, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn topological_order_of(&self, name: N) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::TopologicalOrder {
+            name: Some(name),
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn has_cycle(&self) -> bool {
+        let (tx, rx) = bounded(1);
+        let req = Message::HasCycle { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_many(&self, names: Vec<N>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeMany { names, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_many(&self, names: Vec<N>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeMany { names, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_closure(&self, name: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesClosure { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_closure(&self, name: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsClosure { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn all_paths(&self, from: N, to: N, max_depth: usize) -> Vec<Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::AllPaths {
+            from,
+            to,
+            max_depth,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn distance(&self, from: N, to: N) -> Option<usize> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Distance { from, to, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn common_dependencies(&self, a: N, b: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::CommonDependencies { a, b, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn common_dependents(&self, a: N, b: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::CommonDependents { a, b, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn diff_dependencies(&self, a: N, b: N) -> (HashSet<N>, HashSet<N>) {
+        let (tx, rx) = bounded(1);
+        let req = Message::DiffDependencies { a, b, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn subgraph_between(&self, sources: Vec<N>, sinks: Vec<N>) -> Vec<(N, N)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::SubgraphBetween { sources, sinks, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_count(&self, name: N) -> usize {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCount {
+            name,
+            cascade: true,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_count(&self, name: N) -> usize {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCount {
+            name,
+            cascade: true,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn top_dependents(&self, n: usize) -> Vec<(N, usize)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::TopDependents { n, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_filtered(&self, name: N, types: &[NodeType]) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let types = types.to_vec();
+        let req = Message::DependenciesCascadeFiltered { name, types, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_filtered(&self, name: N, types: &[NodeType]) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let types = types.to_vec();
+        let req = Message::DependentsCascadeFiltered { name, types, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_with_depth(&self, name: N) -> HashMap<N, (usize, Vec<N>)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeWithDepth { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_with_depth(&self, name: N) -> HashMap<N, (usize, Vec<N>)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeWithDepth { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_stream(
+        &self,
+        name: N,
+        batch_size: usize,
+    ) -> Receiver<Vec<(N, Vec<N>)>> {
+        let (tx, rx) = unbounded();
+        let req = Message::DependenciesCascadeStream {
+            name,
+            batch_size,
+            tx,
+        };
+        self.send(req);
+        rx
+    }
+
+    fn dependents_cascade_stream(&self, name: N, batch_size: usize) -> Receiver<Vec<(N, Vec<N>)>> {
+        let (tx, rx) = unbounded();
+        let req = Message::DependentsCascadeStream {
+            name,
+            batch_size,
+            tx,
+        };
+        self.send(req);
+        rx
+    }
+
+    fn dependencies_cascade_until(&self, name: N, stop: Stop<N>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeUntil { name, stop, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_until(&self, name: N, stop: Stop<N>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeUntil { name, stop, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_bounded(
+        &self,
+        name: N,
+        max_nodes: usize,
+    ) -> Bounded<HashMap<N, Vec<N>>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeBounded {
+            name,
+            max_nodes,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_bounded(&self, name: N, max_nodes: usize) -> Bounded<HashMap<N, Vec<N>>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeBounded {
+            name,
+            max_nodes,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn cheapest_path(&self, from: N, to: N) -> Option<(Weight, Vec<N>)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::CheapestPath { from, to, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn component(&self, name: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Component { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn components(&self) -> Vec<HashSet<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Components { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn strongly_connected_components(&self) -> Vec<HashSet<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::StronglyConnectedComponents { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn condensation(&self) -> Condensation<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Condensation { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn transitive_reduction(&self) -> Vec<(N, N)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::TransitiveReduction { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn siblings(&self, name: N) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Siblings { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn is_upstream_of(&self, a: N, b: N) -> bool {
+        let (tx, rx) = bounded(1);
+        let req = Message::IsUpstreamOf { a, b, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn lowest_common_dependencies(&self, a: N, b: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::LowestCommonDependencies { a, b, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn max_depth(&self, name: N) -> Option<usize> {
+        let (tx, rx) = bounded(1);
+        let req = Message::MaxDepth { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn critical_path(&self, name: N) -> Option<Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::CriticalPath { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn stats(&self) -> Stats {
+        let (tx, rx) = bounded(1);
+        let req = Message::Stats { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn critical_nodes(&self, sink: N) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::CriticalNodes { sink, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn shared_upstream(&self, names: Vec<N>) -> HashSet<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::SharedUpstream { names, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_excluding(&self, name: N, exclude: Vec<N>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeExcluding { name, exclude, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_excluding(&self, name: N, exclude: Vec<N>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeExcluding { name, exclude, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn now(&self) -> Timestamp {
+        let (tx, rx) = bounded(1);
+        let req = Message::Now { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_at(&self, name: N, time: Timestamp) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesAt { name, time, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_at(&self, name: N, time: Timestamp) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeAt { name, time, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn diff(&self, name: N, from: Timestamp, to: Timestamp) -> EdgeDiff<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Diff { name, from, to, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn audit(&self, name: N) -> Vec<AuditEntry<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Audit { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn register_query(&self, spec: QuerySpec<N>) -> QueryHandle {
+        let (tx, rx) = bounded(1);
+        let req = Message::RegisterQuery { spec, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn poll_query(&self, handle: QueryHandle) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::PollQuery { handle, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn subscribe_query(&self, handle: QueryHandle) -> Receiver<ChangeBatch<N>> {
+        let (tx, rx) = unbounded();
+        let req = Message::SubscribeQuery { handle, tx };
+        self.send(req);
+        rx
+    }
+
+    fn release_query(&self, handle: QueryHandle) {
+        let req = Message::ReleaseQuery { handle };
+        self.send(req);
+    }
+
+    fn tag_snapshot(&self, label: &str) -> Timestamp {
+        let (tx, rx) = bounded(1);
+        let req = Message::TagSnapshot {
+            label: label.to_string(),
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn snapshot(&self, label: &str) -> Option<Timestamp> {
+        let (tx, rx) = bounded(1);
+        let req = Message::Snapshot {
+            label: label.to_string(),
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn edge_history(&self, from: N, to: N) -> Vec<(Timestamp, isize)> {
+        let (tx, rx) = bounded(1);
+        let req = Message::EdgeHistory { from, to, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn last_modified(&self, name: N) -> Option<Timestamp> {
+        let (tx, rx) = bounded(1);
+        let req = Message::LastModified { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn changed_since(&self, time: Timestamp) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::ChangedSince { time, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_bitemporal(
+        &self,
+        name: N,
+        valid: ValidTime,
+        time: Timestamp,
+    ) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeBitemporal {
+            name,
+            valid,
+            time,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn compact(&self) {
+        let (tx, rx) = bounded(1);
+        let req = Message::Compact { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn compaction_stats(&self) -> CompactionStats {
+        let (tx, rx) = bounded(1);
+        let req = Message::CompactionStats { tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn save_snapshot(&self, path: &Path) -> io::Result<()> {
+        let (tx, rx) = bounded(1);
+        let path = path.to_path_buf();
+        let req = Message::SaveSnapshot { path, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn export_graphml(&self) -> String
+    where
+        N: fmt::Display,
+    {
+        let (tx, rx) = bounded(1);
+        let id = Arc::new(|name: &N| name.to_string());
+        let req = Message::ExportGraphml { id, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn export_gexf(&self) -> String
+    where
+        N: fmt::Display,
+    {
+        let (tx, rx) = bounded(1);
+        let id = Arc::new(|name: &N| name.to_string());
+        let req = Message::ExportGexf { id, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn backup_since(&self, time: Timestamp, path: &Path) -> io::Result<()> {
+        let (tx, rx) = bounded(1);
+        let path = path.to_path_buf();
+        let req = Message::BackupSince { time, path, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn apply_backup(&self, path: &Path) -> io::Result<()> {
+        // validated as a whole, a truncated backup applies nothing
+        let bytes = std::fs::read(path)?;
+        let changes = records::decode_all(&bytes)?;
+        let req = Message::ApplyBackup { changes };
+        self.send(req);
+        Ok(())
+    }
+
+    fn dependencies_cascade_with_metadata(
+        &self,
+        name: N,
+    ) -> (HashMap<N, Vec<N>>, HashMap<N, Metadata>) {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeWithMetadata { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_with_metadata(
+        &self,
+        name: N,
+    ) -> (HashMap<N, Vec<N>>, HashMap<N, Metadata>) {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeWithMetadata { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_at_depth(&self, name: N, depth: usize) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesAtDepth { name, depth, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_at_depth(&self, name: N, depth: usize) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsAtDepth { name, depth, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesOfKind { name, kinds, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> Vec<N> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsOfKind { name, kinds, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependencies_cascade_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependenciesCascadeOfKind { name, kinds, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn dependents_cascade_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> HashMap<N, Vec<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::DependentsCascadeOfKind { name, kinds, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn upsert_valid(&self, name: N, dependencies: Vec<(N, Range<ValidTime>)>) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|(d, valid)| (d, EdgeKind::new(), 1, (valid.start, valid.end)))
+            .collect();
+        let req = Message::Upsert { name, dependencies };
+        self.send(req);
+    }
+
+    fn upsert_with_ttl(&self, name: N, dependencies: Vec<N>, ttl: Duration) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|d| (d, EdgeKind::new(), 1, ALWAYS))
+            .collect();
+        let req = Message::UpsertWithTtl {
+            name,
+            dependencies,
+            ttl,
+        };
+        self.send(req);
+    }
+
+    fn upsert_at(&self, name: N, dependencies: Vec<N>, time: Timestamp) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|d| (d, EdgeKind::new(), 1, ALWAYS))
+            .collect();
+        let req = Message::UpsertAt {
+            name,
+            dependencies,
+            time,
+        };
+        self.send(req);
+    }
+
+    fn upsert_typed(&self, name: N, dependencies: Vec<(N, EdgeKind)>) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|(d, kind)| (d, kind, 1, ALWAYS))
+            .collect();
+        let req = Message::Upsert { name, dependencies };
+        self.send(req);
+    }
+
+    fn upsert_weighted(&self, name: N, dependencies: Vec<(N, Weight)>) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|(d, weight)| (d, EdgeKind::new(), weight, ALWAYS))
+            .collect();
+        let req = Message::Upsert { name, dependencies };
+        self.send(req);
+    }
+
+    fn try_upsert(&self, name: N, dependencies: Vec<N>) -> Result<(), Error<N>> {
+        let (tx, rx) = bounded(1);
+        let dependencies = dependencies
+            .into_iter()
+            .map(|d| (d, EdgeKind::new(), 1, ALWAYS))
+            .collect();
+        let req = Message::TryUpsert {
+            name,
+            dependencies,
+            tx,
+        };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn delete_at(&self, name: N, time: Timestamp) {
+        let req = Message::DeleteAt { name, time };
+        self.send(req);
+    }
+
+    fn upsert_by(&self, name: N, dependencies: Vec<N>, by: Attribution) {
+        let dependencies = dependencies
+            .into_iter()
+            .map(|d| (d, EdgeKind::new(), 1, ALWAYS))
+            .collect();
+        let req = Message::UpsertBy {
+            name,
+            dependencies,
+            by,
+        };
+        self.send(req);
+    }
+
+    fn delete_by(&self, name: N, by: Attribution) {
+        let req = Message::DeleteBy { name, by };
+        self.send(req);
+    }
+
+    fn try_delete(&self, name: N) -> Result<(), Error<N>> {
+        let (tx, rx) = bounded(1);
+        let req = Message::TryDelete { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn rollback(&self, n: usize) {
+        let req = Message::Rollback { n };
+        self.send(req);
+    }
+
+    fn soft_delete(&self, name: N) {
+        let req = Message::SoftDelete { name };
+        self.send(req);
+    }
+
+    fn restore(&self, name: N) {
+        let req = Message::Restore { name };
+        self.send(req);
+    }
+
+    fn alias(&self, alias: N, target: N) {
+        let req = Message::Alias { alias, target };
+        self.send(req);
+    }
+
+    fn rename(&self, old: N, new: N) {
+        let req = Message::Rename { old, new };
+        self.send(req);
+    }
+
+    fn merge(&self, into: N, from: N) {
+        let req = Message::Merge { into, from };
+        self.send(req);
+    }
+
+    fn node_type(&self, name: N) -> Option<NodeType> {
+        let (tx, rx) = bounded(1);
+        let req = Message::GetNodeType { name, tx };
+        self.send(req);
+        rx.recv().unwrap()
+    }
+
+    fn set_node_type(&self, name: N, node_type: Option<NodeType>) {
+        let req = Message::SetNodeType { name, node_type };
+        self.send(req);
+    }
+}
+
+/// A message together with the span of the caller that sent it, which the
+/// worker handles it in, and for async callers what wakes them once
+/// replied to.
+#[derive(Clone)]
+struct Envelope<N> {
+    message: Message<N>,
+    span: tracing::Span,
+    wake: Option<Wake>,
+}
+
+impl<N> Envelope<N> {
+    fn new(message: Message<N>) -> Self {
+        Envelope {
+            message,
+            span: tracing::Span::current(),
+            wake: None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Message<N> {
+    Exists {
+        name: N,
+        tx: Sender<bool>,
+    },
+    Nodes {
+        tx: Sender<Vec<N>>,
+    },
+    Edges {
+        tx: Sender<Vec<(N, N)>>,
+    },
+    Roots {
+        tx: Sender<Vec<N>>,
+    },
+    Leaves {
+        tx: Sender<Vec<N>>,
+    },
+    TopologicalOrder {
+        name: Option<N>,
+        tx: Sender<Vec<N>>,
+    },
+    HasCycle {
+        tx: Sender<bool>,
+    },
+    Dependencies {
+        name: N,
+        tx: Sender<Vec<N>>,
+    },
+    Dependents {
+        name: N,
+        tx: Sender<Vec<N>>,
+    },
+    DependenciesCascade {
+        name: N,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependenciesCascadeMany {
+        names: Vec<N>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsCascade {
+        name: N,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsCascadeMany {
+        names: Vec<N>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependenciesClosure {
+        name: N,
+        tx: Sender<HashSet<N>>,
+    },
+    DependentsClosure {
+        name: N,
+        tx: Sender<HashSet<N>>,
+    },
+    AllPaths {
+        from: N,
+        to: N,
+        max_depth: usize,
+        tx: Sender<Vec<Vec<N>>>,
+    },
+    Distance {
+        from: N,
+        to: N,
+        tx: Sender<Option<usize>>,
+    },
+    CommonDependencies {
+        a: N,
+        b: N,
+        tx: Sender<HashSet<N>>,
+    },
+    CommonDependents {
+        a: N,
+        b: N,
+        tx: Sender<HashSet<N>>,
+    },
+    DiffDependencies {
+        a: N,
+        b: N,
+        tx: Sender<(HashSet<N>, HashSet<N>)>,
+    },
+    Impact {
+        name: N,
+        tx: Sender<Impact<N>>,
+    },
+    SubgraphBetween {
+        sources: Vec<N>,
+        sinks: Vec<N>,
+        tx: Sender<Vec<(N, N)>>,
+    },
+    DependenciesCount {
+        name: N,
+        cascade: bool,
+        tx: Sender<usize>,
+    },
+    DependentsCount {
+        name: N,
+        cascade: bool,
+        tx: Sender<usize>,
+    },
+    TopDependents {
+        n: usize,
+        tx: Sender<Vec<(N, usize)>>,
+    },
+    DependenciesCascadeFiltered {
+        name: N,
+        types: Vec<NodeType>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsCascadeFiltered {
+        name: N,
+        types: Vec<NodeType>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependenciesCascadeWithDepth {
+        name: N,
+        tx: Sender<HashMap<N, (usize, Vec<N>)>>,
+    },
+    DependentsCascadeWithDepth {
+        name: N,
+        tx: Sender<HashMap<N, (usize, Vec<N>)>>,
+    },
+    DependenciesCascadeStream {
+        name: N,
+        batch_size: usize,
+        tx: Sender<Vec<(N, Vec<N>)>>,
+    },
+    DependentsCascadeStream {
+        name: N,
+        batch_size: usize,
+        tx: Sender<Vec<(N, Vec<N>)>>,
+    },
+    DependenciesCascadeUntil {
+        name: N,
+        stop: Stop<N>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsCascadeUntil {
+        name: N,
+        stop: Stop<N>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependenciesCascadeBounded {
+        name: N,
+        max_nodes: usize,
+        tx: Sender<Bounded<HashMap<N, Vec<N>>>>,
+    },
+    DependentsCascadeBounded {
+        name: N,
+        max_nodes: usize,
+        tx: Sender<Bounded<HashMap<N, Vec<N>>>>,
+    },
+    CheapestPath {
+        from: N,
+        to: N,
+        tx: Sender<Option<(Weight, Vec<N>)>>,
+    },
+    Component {
+        name: N,
+        tx: Sender<HashSet<N>>,
+    },
+    Components {
+        tx: Sender<Vec<HashSet<N>>>,
+    },
+    StronglyConnectedComponents {
+        tx: Sender<Vec<HashSet<N>>>,
+    },
+    Condensation {
+        tx: Sender<Condensation<N>>,
+    },
+    TransitiveReduction {
+        tx: Sender<Vec<(N, N)>>,
+    },
+    Siblings {
+        name: N,
+        tx: Sender<Vec<N>>,
+    },
+    Orphans {
+        tx: Sender<Vec<N>>,
+    },
+    IsUpstreamOf {
+        a: N,
+        b: N,
+        tx: Sender<bool>,
+    },
+    LowestCommonDependencies {
+        a: N,
+        b: N,
+        tx: Sender<HashSet<N>>,
+    },
+    MaxDepth {
+        name: N,
+        tx: Sender<Option<usize>>,
+    },
+    CriticalPath {
+        name: N,
+        tx: Sender<Option<Vec<N>>>,
+    },
+    Stats {
+        tx: Sender<Stats>,
+    },
+    CriticalNodes {
+        sink: N,
+        tx: Sender<HashSet<N>>,
+    },
+    SharedUpstream {
+        names: Vec<N>,
+        tx: Sender<HashSet<N>>,
+    },
+    DependenciesCascadeExcluding {
+        name: N,
+        exclude: Vec<N>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsCascadeExcluding {
+        name: N,
+        exclude: Vec<N>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    Now {
+        tx: Sender<Timestamp>,
+    },
+    DependenciesAt {
+        name: N,
+        time: Timestamp,
+        tx: Sender<Vec<N>>,
+    },
+    DependentsCascadeAt {
+        name: N,
+        time: Timestamp,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    Diff {
+        name: N,
+        from: Timestamp,
+        to: Timestamp,
+        tx: Sender<EdgeDiff<N>>,
+    },
+    SubscribeDependentsCascade {
+        name: N,
+        tx: Sender<ChangeBatch<N>>,
+    },
+    Changes {
+        tx: Sender<(N, N, isize, Timestamp)>,
+    },
+    Audit {
+        name: N,
+        tx: Sender<Vec<AuditEntry<N>>>,
+    },
+    RegisterQuery {
+        spec: QuerySpec<N>,
+        tx: Sender<QueryHandle>,
+    },
+    PollQuery {
+        handle: QueryHandle,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    SubscribeQuery {
+        handle: QueryHandle,
+        tx: Sender<ChangeBatch<N>>,
+    },
+    ReleaseQuery {
+        handle: QueryHandle,
+    },
+    TagSnapshot {
+        label: String,
+        tx: Sender<Timestamp>,
+    },
+    Snapshot {
+        label: String,
+        tx: Sender<Option<Timestamp>>,
+    },
+    EdgeHistory {
+        from: N,
+        to: N,
+        tx: Sender<Vec<(Timestamp, isize)>>,
+    },
+    LastModified {
+        name: N,
+        tx: Sender<Option<Timestamp>>,
+    },
+    ChangedSince {
+        time: Timestamp,
+        tx: Sender<Vec<N>>,
+    },
+    DependenciesCascadeBitemporal {
+        name: N,
+        valid: ValidTime,
+        time: Timestamp,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    Compact {
+        tx: Sender<()>,
+    },
+    CompactionStats {
+        tx: Sender<CompactionStats>,
+    },
+    SaveSnapshot {
+        path: PathBuf,
+        tx: Sender<io::Result<()>>,
+    },
+    ExportGraphml {
+        id: Arc<dyn Fn(&N) -> String + Send + Sync>,
+        tx: Sender<String>,
+    },
+    ExportGexf {
+        id: Arc<dyn Fn(&N) -> String + Send + Sync>,
+        tx: Sender<String>,
+    },
+    BackupSince {
+        time: Timestamp,
+        path: PathBuf,
+        tx: Sender<io::Result<()>>,
+    },
+    ApplyBackup {
+        changes: Vec<Change<N>>,
+    },
+    DependenciesCascadeWithMetadata {
+        name: N,
+        tx: Sender<(HashMap<N, Vec<N>>, HashMap<N, Metadata>)>,
+    },
+    DependentsCascadeWithMetadata {
+        name: N,
+        tx: Sender<(HashMap<N, Vec<N>>, HashMap<N, Metadata>)>,
+    },
+    DependenciesOfKind {
+        name: N,
+        kinds: Vec<EdgeKind>,
+        tx: Sender<Vec<N>>,
+    },
+    DependentsOfKind {
+        name: N,
+        kinds: Vec<EdgeKind>,
+        tx: Sender<Vec<N>>,
+    },
+    DependenciesCascadeOfKind {
+        name: N,
+        kinds: Vec<EdgeKind>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsCascadeOfKind {
+        name: N,
+        kinds: Vec<EdgeKind>,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    Neighborhood {
+        name: N,
+        k: usize,
+        tx: Sender<Impact<N>>,
+    },
+    DependenciesAtDepth {
+        name: N,
+        depth: usize,
+        tx: Sender<Vec<N>>,
+    },
+    DependentsAtDepth {
+        name: N,
+        depth: usize,
+        tx: Sender<Vec<N>>,
+    },
+    DependenciesK {
+        name: N,
+        k: usize,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    DependentsK {
+        name: N,
+        k: usize,
+        tx: Sender<HashMap<N, Vec<N>>>,
+    },
+    Upsert {
+        name: N,
+        dependencies: Vec<Dependency<N>>,
+    },
+    UpsertWithTtl {
+        name: N,
+        dependencies: Vec<Dependency<N>>,
+        ttl: Duration,
+    },
+    UpsertAt {
+        name: N,
+        dependencies: Vec<Dependency<N>>,
+        time: Timestamp,
+    },
+    TryUpsert {
+        name: N,
+        dependencies: Vec<Dependency<N>>,
+        tx: Sender<Result<(), Error<N>>>,
+    },
+    UpsertBatch {
+        batch: Vec<(N, Vec<Dependency<N>>)>,
+    },
+    AddDependency {
+        name: N,
+        dependency: N,
+    },
+    RemoveDependency {
+        name: N,
+        dependency: N,
+    },
+    Delete {
+        name: N,
+    },
+    DeleteAt {
+        name: N,
+        time: Timestamp,
+    },
+    UpsertBy {
+        name: N,
+        dependencies: Vec<Dependency<N>>,
+        by: Attribution,
+    },
+    DeleteBy {
+        name: N,
+        by: Attribution,
+    },
+    TryDelete {
+        name: N,
+        tx: Sender<Result<(), Error<N>>>,
+    },
+    Rollback {
+        n: usize,
+    },
+    SoftDelete {
+        name: N,
+    },
+    Restore {
+        name: N,
+    },
+    Alias {
+        alias: N,
+        target: N,
+    },
+    Rename {
+        old: N,
+        new: N,
+    },
+    Merge {
+        into: N,
+        from: N,
+    },
+    GetNodeType {
+        name: N,
+        tx: Sender<Option<NodeType>>,
+    },
+    SetNodeType {
+        name: N,
+        node_type: Option<NodeType>,
+    },
+    GetMetadata {
+        name: N,
+        tx: Sender<Option<Metadata>>,
+    },
+    SetMetadata {
+        name: N,
+        metadata: Metadata,
+    },
+}
+
+type MetaVal = Vec<(String, String)>;
+/// Time, actor, reason and new dependencies of an attributed change.
+type AuditVal<N> = (Timestamp, String, String, Option<Vec<N>>);
+/// Receivers of the changes of one standing query.
+type Subscribers<N> = Rc<RefCell<Vec<Sender<ChangeBatch<N>>>>>;
+/// A node with its new dependency list, `None` once deleted, as stored by
+/// the write-ahead log and backups.
+type Change<N> = (N, Option<Dependencies<N>>);
+/// The dependency list a journaled write replaced, see `Context::write`.
+#[derive(Clone)]
+enum Replaced<N> {
+    /// Written earlier at the same time, not yet in the traces.
+    Pending(Option<Dependencies<N>>),
+    /// Whatever the traces held before the time.
+    Before(Timestamp),
+}
+/// Valid-time interval of an edge, start inclusive and end exclusive.
+type Validity = (ValidTime, ValidTime);
+type Dependency<N> = (N, EdgeKind, Weight, Validity);
+/// A dependency list as the traces hold it, cloned by reference.
+type Dependencies<N> = Shared<Dependency<N>>;
+/// What a call asks the standing queries about: which way, `true` towards
+/// the dependencies, from which name. See `Context::ask`.
+type Ask<N> = (bool, N);
+/// Like `Ask`, within a number of hops.
+type HopAsk<N> = (bool, N, usize);
+
+/// Validity of edges upserted without one.
+const ALWAYS: Validity = (ValidTime::MIN, ValidTime::MAX);
+type Spine<N, V = N> = OrdValSpine<N, V, Timestamp, isize>;
+type TraceHandle<N, V = N> = TraceAgent<Spine<N, V>>;
+
+struct Traces<N: Key> {
+    /// Every upserted node, soft-deleted ones included. Updates read this,
+    /// queries read `nodes`.
+    upserted: TraceHandle<N, Dependencies<N>>,
+    nodes: TraceHandle<N, Dependencies<N>>,
+    upstream: TraceHandle<N>,
+    downstream: TraceHandle<N>,
+    upstream_typed: TraceHandle<N, (N, EdgeKind)>,
+    downstream_typed: TraceHandle<N, (N, EdgeKind)>,
+    upstream_weighted: TraceHandle<N, (N, Weight)>,
+    upstream_valid: TraceHandle<N, (N, Validity)>,
+    metadata: TraceHandle<N, MetaVal>,
+    types: TraceHandle<N, NodeType>,
+    /// Soft-deleted nodes, see `soft_delete`.
+    tombstones: TraceHandle<N, ()>,
+    /// Append-only record of attributed changes.
+    audit: TraceHandle<N, AuditVal<N>>,
+    /// Every `(name, reachable)` pair, see `Config::maintain_closure`.
+    upstream_closure: Option<TraceHandle<N>>,
+    downstream_closure: Option<TraceHandle<N>>,
+    /// Answers of the standing queries to the asks of the calls being
+    /// handled, held by every worker. `lookups` are the direct edges,
+    /// `cascades` and `hops` every `(node, dependency)` edge reached.
+    lookups: TraceHandle<Ask<N>, N>,
+    cascades: TraceHandle<Ask<N>, (N, N)>,
+    hops: TraceHandle<HopAsk<N>, (N, N)>,
+}
+
+struct Context<N: Key> {
+    input: Handle<Timestamp, (N, Option<Dependencies<N>>, Timestamp)>,
+    meta_input: Handle<Timestamp, (N, Option<MetaVal>, Timestamp)>,
+    type_input: Handle<Timestamp, (N, Option<NodeType>, Timestamp)>,
+    audit_input: Handle<Timestamp, ((N, AuditVal<N>), Timestamp, isize)>,
+    tombstone_input: Handle<Timestamp, (N, Option<()>, Timestamp)>,
+    lookup_input: Handle<Timestamp, (Ask<N>, Timestamp, isize)>,
+    cascade_input: Handle<Timestamp, (Ask<N>, Timestamp, isize)>,
+    hop_input: Handle<Timestamp, (HopAsk<N>, Timestamp, isize)>,
+    counter: Timestamp,
+    probe: ProbeHandle<Timestamp>,
+    config: Config,
+    /// Alternative names, each mapped to the name it stands for.
+    aliases: HashMap<N, N>,
+    /// Number of installed subscription dataflows, see `subscribe_cascade`,
+    /// and of the receivers of standing queries still listening.
+    subscriptions: Rc<Cell<usize>>,
+    /// When each retained time was reached, for `Retention::Duration`.
+    history: VecDeque<(Timestamp, Instant)>,
+    /// Times labelled by `tag_snapshot`, kept readable by compaction.
+    tags: HashMap<String, Timestamp>,
+    /// Sequence number of the message being handled.
+    mutation: u64,
+    /// The dependency lists replaced by the latest mutations, grouped by
+    /// mutation, see `Config::journal`.
+    journal: VecDeque<(u64, Vec<(N, Replaced<N>)>)>,
+    /// Dependency lists written at the current time, not yet in the traces.
+    pending: HashMap<N, Option<Dependencies<N>>>,
+    /// Result traces of registered queries with their dataflow, indexed by
+    /// `QueryHandle`. `None` once released.
+    standing: Vec<Option<(TraceHandle<N>, Subscribers<N>, usize)>>,
+    /// Dataflows that outlive the call installing them, see
+    /// `drop_dataflows`.
+    kept: HashSet<usize>,
+    /// Kept subscription dataflows whose receiver hung up, see `release`.
+    abandoned: Rc<RefCell<Vec<usize>>>,
+    /// When each node upserted by `upsert_with_ttl` is due to be deleted.
+    expiries: HashMap<N, Instant>,
+    /// The write-ahead log, see `Config::wal`.
+    wal: Option<File>,
+    /// Where every change is written through to, see `with_storage`.
+    storage: Option<Box<dyn Storage<N>>>,
+    /// Whether this is the first worker of all, which alone feeds the inputs.
+    leader: bool,
+    /// Whether this is the first worker of its process, which alone answers.
+    replies: bool,
+}
+
+impl<N: Key> Context<N> {
+    fn new(config: Config) -> Self {
+        let input: Handle<Timestamp, _> = InputHandle::new();
+        let meta_input = InputHandle::new();
+        let type_input = InputHandle::new();
+        let audit_input = InputHandle::new();
+        let tombstone_input = InputHandle::new();
+        let lookup_input = InputHandle::new();
+        let cascade_input = InputHandle::new();
+        let hop_input = InputHandle::new();
+        let counter = *input.time();
+        let probe = ProbeHandle::new();
+        Context {
+            input,
+            meta_input,
+            type_input,
+            audit_input,
+            tombstone_input,
+            lookup_input,
+            cascade_input,
+            hop_input,
+            counter,
+            probe,
+            config,
+            aliases: HashMap::new(),
+            subscriptions: Rc::new(Cell::new(0)),
+            history: VecDeque::new(),
+            tags: HashMap::new(),
+            mutation: 0,
+            journal: VecDeque::new(),
+            pending: HashMap::new(),
+            standing: vec![],
+            kept: HashSet::new(),
+            abandoned: Rc::new(RefCell::new(vec![])),
+            expiries: HashMap::new(),
+            wal: None,
+            storage: None,
+            leader: true,
+            replies: true,
+        }
+    }
+
+    /// Answers a call, which only one worker of each process does.
+    fn reply<T>(&self, tx: &Sender<T>, value: T) {
+        if self.replies {
+            tx.send(value).unwrap();
+        }
+    }
+
+    /// Wakes the async caller of a message once it has been replied to.
+    fn wake(&self, wake: Option<Wake>) {
+        if let (true, Some(wake)) = (self.replies, wake) {
+            wake.wake();
+        }
+    }
+
+    /// Drops the dataflows installed since `first` and with them the traces
+    /// they imported, except those `kept` by subscriptions and registered
+    /// queries. Every worker installs and drops the same ones.
+    fn drop_dataflows<A: Allocate>(&self, first: usize, worker: &mut Worker<A>) {
+        for index in first..worker.next_dataflow_index() {
+            if !self.kept.contains(&index) {
+                worker.drop_dataflow(index);
+            }
+        }
+    }
+
+    /// Drops the subscription dataflows of `indices`, whose receivers hung
+    /// up. The other processes of a cluster can't tell, so there they stay
+    /// installed, only no longer keeping time advancing.
+    fn release<A: Allocate>(&mut self, indices: Vec<usize>, worker: &mut Worker<A>) {
+        for index in indices {
+            if self.kept.remove(&index) {
+                self.subscriptions.set(self.subscriptions.get() - 1);
+                if self.config.processes() == 1 {
+                    worker.drop_dataflow(index);
+                }
+            }
+        }
+    }
+
+    fn resolve(&self, name: N) -> N {
+        match self.aliases.get(&name) {
+            Some(target) => target.clone(),
+            None => name,
+        }
+    }
+
+    fn resolve_all(&self, names: Vec<N>) -> Vec<N> {
+        names.into_iter().map(|name| self.resolve(name)).collect()
+    }
+
+    fn alias(&mut self, alias: N, target: N) {
+        let target = self.resolve(target);
+        if alias == target {
+            return;
+        }
+        for existing in self.aliases.values_mut() {
+            if *existing == alias {
+                *existing = target.clone();
+            }
+        }
+        self.aliases.insert(alias, target);
+    }
+
+    fn advance<A: Allocate>(&mut self, worker: &mut Worker<A>) {
+        self.pending.clear();
+        self.counter += 1;
+        self.advance_inputs();
+        if let Retention::Duration(duration) = self.config.retention {
+            let now = Instant::now();
+            self.history.push_back((self.counter, now));
+            while self
+                .history
+                .front()
+                .map_or(false, |d| now.duration_since(d.1) > duration)
+            {
+                self.history.pop_front();
+            }
+        }
+        worker.step_while(|| self.probe.less_than(self.input.time()));
+    }
+
+    fn advance_inputs(&mut self) {
+        self.input.advance_to(self.counter);
+        self.meta_input.advance_to(self.counter);
+        self.type_input.advance_to(self.counter);
+        self.audit_input.advance_to(self.counter);
+        self.tombstone_input.advance_to(self.counter);
+        self.lookup_input.advance_to(self.counter);
+        self.cascade_input.advance_to(self.counter);
+        self.hop_input.advance_to(self.counter);
+    }
+
+    /// Deletes every node whose TTL has passed. A node that can't be deleted,
+    /// see `DanglingPolicy::Reject`, is kept for good.
+    fn expire<A: Allocate>(&mut self, traces: &mut Traces<N>, worker: &mut Worker<A>) {
+        // without TTLs there is no need for a clock, which wasm32 lacks
+        if self.expiries.is_empty() {
+            return;
+        }
+        self.expire_at(traces, Instant::now(), worker)
+    }
+
+    /// Whether the TTL of any node has passed by `now`.
+    fn expiring(&self, now: Instant) -> bool {
+        self.expiries.values().any(|deadline| *deadline <= now)
+    }
+
+    fn expire_at<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        now: Instant,
+        worker: &mut Worker<A>,
+    ) {
+        let expired: Vec<N> = self
+            .expiries
+            .iter()
+            .filter(|(_, deadline)| **deadline <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        self.mutation += 1;
+        let first = worker.next_dataflow_index();
+        for name in expired {
+            self.expiries.remove(&name);
+            let _ = self.delete(traces, name, worker);
+        }
+        self.advance(worker);
+        self.drop_dataflows(first, worker);
+    }
+
+    /// Moves the inputs forward to `time` so the next updates land there,
+    /// earlier times are left as they are.
+    fn skip_to(&mut self, time: Timestamp) {
+        if time > self.counter {
+            self.counter = time;
+            self.advance_inputs();
+        }
+    }
+
+    /// Appends an attributed change of `name` to the audit collection.
+    fn record(&mut self, name: N, dependencies: Option<Vec<N>>, by: Attribution) {
+        let event = (self.counter, by.actor, by.reason, dependencies);
+        if self.leader {
+            self.audit_input.send(((name, event), self.counter, 1));
+        }
+    }
+
+    fn compact<K: ExchangeData, V: ExchangeData>(&self, trace: &mut TraceHandle<K, V>) {
+        let frontier = &[self.oldest()];
+        trace.set_physical_compaction(AntichainRef::new(frontier));
+        trace.set_logical_compaction(AntichainRef::new(frontier));
+    }
+
+    /// The closest time to `time` that compaction has kept readable.
+    fn retained(&self, time: Timestamp) -> Timestamp {
+        time.clamp(self.oldest(), self.counter)
+    }
+
+    /// The oldest time compaction keeps readable, see `Config::retention`.
+    fn oldest(&self) -> Timestamp {
+        let oldest = match self.config.retention {
+            Retention::Versions(n) => self.counter.saturating_sub(n),
+            Retention::Duration(_) => self.history.front().map_or(self.counter, |d| d.0),
+        };
+        let journaled = self
+            .journal
+            .iter()
+            .flat_map(|(_, writes)| writes)
+            .filter_map(|(_, replaced)| match replaced {
+                Replaced::Before(time) => Some(time.saturating_sub(1)),
+                Replaced::Pending(_) => None,
+            });
+        self.tags
+            .values()
+            .copied()
+            .chain(journaled)
+            .fold(oldest, |oldest, time| oldest.min(time))
+    }
+
+    fn upsert<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        name: N,
+        dependencies: Vec<Dependency<N>>,
+        worker: &mut Worker<A>,
+    ) -> Result<(), Error<N>> {
+        if self.config.reject_cycles {
+            let targets = dependencies.iter().map(|d| d.0.clone()).collect();
+            if self.creates_cycle(&mut traces.upstream, name.clone(), targets, worker) {
+                return Err(Error::Cycle(name));
+            }
+        }
+        self.write(name, Some(dependencies.into()));
+        Ok(())
+    }
+
+    /// Upserts every entry of `batch` at the same time. With
+    /// `Config::reject_cycles` the entries closing a cycle are dropped, the
+    /// whole batch being checked at once.
+    fn upsert_batch<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        mut batch: Vec<(N, Vec<Dependency<N>>)>,
+        worker: &mut Worker<A>,
+    ) {
+        // dropping an entry brings back the edges it replaced, which may in
+        // turn close a cycle with the remaining ones
+        while self.config.reject_cycles {
+            let closing = self.closes_cycles(&mut traces.upstream, &batch, worker);
+            if closing.is_empty() {
+                break;
+            }
+            batch.retain(|(name, _)| !closing.contains(name));
+        }
+        for (name, dependencies) in batch {
+            self.write(name, Some(dependencies.into()));
+        }
+    }
+
+    /// Sends a new dependency list of `name`, journaling the one it replaces
+    /// for `rollback` when `Config::journal` asks for it. Unless written at
+    /// this same time, the replaced list is only read back by `rollback`.
+    fn write(&mut self, name: N, dependencies: Option<Dependencies<N>>) {
+        if self.config.journal > 0 {
+            let previous = match self.pending.get(&name) {
+                Some(previous) => Replaced::Pending(previous.clone()),
+                None => Replaced::Before(self.counter),
+            };
+            if self.journal.back().map(|m| m.0) != Some(self.mutation) {
+                self.journal.push_back((self.mutation, vec![]));
+                if self.journal.len() > self.config.journal {
+                    self.journal.pop_front();
+                }
+            }
+            self.journal
+                .back_mut()
+                .unwrap()
+                .1
+                .push((name.clone(), previous));
+            self.pending.insert(name.clone(), dependencies.clone());
+        }
+        self.expiries.remove(&name);
+        if let Some(wal) = self.wal.as_mut() {
+            let mut bytes = vec![];
+            records::append(&(&name, &dependencies), &mut bytes);
+            wal.write_all(&bytes)
+                .and_then(|()| wal.sync_data())
+                .expect("failed to append to the write-ahead log");
+        }
+        if let Some(storage) = self.storage.as_mut() {
+            match &dependencies {
+                Some(dependencies) => storage.put(&name, dependencies),
+                None => storage.remove(&name),
+            }
+            .expect("failed to write through to storage");
+        }
+        if self.leader {
+            self.input.send((name, dependencies, self.counter));
+        }
+    }
+
+    /// Applies the latest change of every node in the write-ahead log, then
+    /// keeps appending to it.
+    fn replay(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
+        };
+        let mut wal = OpenOptions::new().create(true).append(true).open(path)?;
+        if bytes.is_empty() {
+            wal.write_all(&records::header())?;
+            wal.sync_data()?;
+            self.wal = Some(wal);
+            return Ok(());
+        }
+        // a record torn by a crash is dropped, everything before it was
+        // applied. It is cut off so that appends don't land behind it.
+        let (changes, torn): (Vec<Change<N>>, _) = records::decode(&bytes)?;
+        if torn > 0 {
+            wal.set_len((bytes.len() - torn) as u64)?;
+            wal.sync_data()?;
+        }
+        let latest: HashMap<_, _> = changes.into_iter().collect();
+        for (name, dependencies) in latest {
+            if dependencies.is_some() {
+                self.input.send((name, dependencies, self.counter));
+            }
+        }
+        self.wal = Some(wal);
+        Ok(())
+    }
+
+    /// Replaces the write-ahead log at `path` by one holding just `nodes`,
+    /// e.g. those of a snapshot just saved.
+    fn rewrite_wal(&mut self, path: &Path, nodes: &[(N, Dependencies<N>)]) -> io::Result<()> {
+        let mut bytes = records::header();
+        for (name, dependencies) in nodes {
+            records::append(&(name, Some(dependencies)), &mut bytes);
+        }
+        // written aside and renamed over the log, so a crash leaves either
+        let tmp = path.with_extension("tmp");
+        let mut file = File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        self.wal = Some(OpenOptions::new().append(true).open(path)?);
+        Ok(())
+    }
+
+    /// Writes the current dependency list of every node changed at or after
+    /// `since`, `None` for a deleted one, to `path`.
+    fn backup_since<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, Dependencies<N>>,
+        since: Timestamp,
+        path: &Path,
+        worker: &mut Worker<A>,
+    ) -> io::Result<()> {
+        let changed = self.changed_since(trace, since, worker);
+        let mut current: HashMap<N, Dependencies<N>> = self
+            .query_many(trace, changed.clone(), worker)
+            .into_iter()
+            .filter_map(|(name, mut values)| Some((name, values.pop()?)))
+            .collect();
+        if !self.replies {
+            return Ok(());
+        }
+        let mut bytes = records::header();
+        for name in changed {
+            let dependencies = current.remove(&name);
+            records::append(&(name, dependencies), &mut bytes);
+        }
+        std::fs::write(path, bytes)
+    }
+
+    /// Restores the dependency lists replaced by the latest `n` journaled
+    /// mutations, at a new time. They are written like any other change, the
+    /// rollback itself is not journaled.
+    fn rollback<A: Allocate>(&mut self, traces: &mut Traces<N>, n: usize, worker: &mut Worker<A>) {
+        // ordered, so that every worker reads them back in the same order
+        let mut replaced = BTreeMap::new();
+        let kept = self.journal.len().saturating_sub(n);
+        for (_, writes) in self.journal.range(kept..).rev() {
+            for (name, previous) in writes.iter().rev() {
+                replaced.insert(name.clone(), previous.clone());
+            }
+        }
+        let mut restored = vec![];
+        for (name, previous) in replaced {
+            let previous = match previous {
+                Replaced::Pending(previous) => previous,
+                Replaced::Before(0) => None,
+                Replaced::Before(time) => self
+                    .query_at(&mut traces.upserted, name.clone(), time - 1, worker)
+                    .pop(),
+            };
+            restored.push((name, previous));
+        }
+        self.journal.truncate(kept);
+        self.advance(worker);
+        for (name, previous) in restored {
+            self.write(name, previous);
+        }
+        if self.journal.back().map(|m| m.0) == Some(self.mutation) {
+            self.journal.pop_back();
+        }
+    }
+
+    /// Whether `name` is reachable from any of `targets`, i.e. whether making
+    /// `name` depend on them would close a cycle.
+    fn creates_cycle<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        targets: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> bool {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = targets
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res = held(
+                &closure(&trace.import(scope), &query)
+                    .concat(&query)
+                    .filter(move |x| *x == name)
+                    .map(|x| (x, ()))
+                    .distinct(),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        !self.read(&mut result_trace).is_empty()
+    }
+
+    /// The names of `batch` that would reach themselves were all of it
+    /// upserted, the later entry of a name replacing the earlier ones.
+    fn closes_cycles<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        batch: &[(N, Vec<Dependency<N>>)],
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        let current = self.counter;
+        let batch: BTreeMap<N, Vec<N>> = batch
+            .iter()
+            .map(|(name, dependencies)| {
+                (
+                    name.clone(),
+                    dependencies.iter().map(|d| d.0.clone()).collect(),
+                )
+            })
+            .collect();
+        let mut result_trace = worker.dataflow(|scope| {
+            let batch = batch
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let names = batch.map(|(name, _)| name);
+            let edges = batch.flat_map(|(name, targets)| {
+                targets
+                    .into_iter()
+                    .map(move |target| (name.clone(), target))
+            });
+            let graph = trace
+                .import(scope)
+                .as_collection(|k, v| (k.clone(), v.clone()))
+                .antijoin(&names)
+                .concat(&edges)
+                .arrange_by_key();
+            // `(name, node)` for every node reached from a name of the batch
+            let reach = edges.iterate(|reach| {
+                reach
+                    .map(|(name, node)| (node, name))
+                    .join_core(&graph.enter(&reach.scope()), |_node, name, next| {
+                        Some((name.clone(), next.clone()))
+                    })
+                    .concat(&edges.enter(&reach.scope()))
+                    .distinct()
+            });
+            let res = held(&reach.filter(|(name, node)| name == node));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query<V: ExchangeData, A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, V>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Vec<V> {
+        let current = self.counter;
+        self.query_at(trace, name, current, worker)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_at<V: ExchangeData, A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, V>,
+        name: N,
+        time: Timestamp,
+        worker: &mut Worker<A>,
+    ) -> Vec<V> {
+        let time = self.retained(time);
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, time, 1))
+                .as_collection();
+            let lineage = held(&trace.import(scope).semijoin(&query));
+
+            lineage.stream.probe_with(&mut self.probe);
+            lineage.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read_at(&mut result_trace, time);
+        result.pop().map(|d| d.1).unwrap_or(vec![])
+    }
+
+    /// Answers `asks` from the standing queries of `build`: adds them to the
+    /// query input, reads their answers once the dataflow has caught up and
+    /// retracts them again. No dataflow is built per call.
+    fn ask<K: ExchangeData, V: ExchangeData, A: Allocate>(
+        &mut self,
+        input: fn(&mut Self) -> &mut Handle<Timestamp, (K, Timestamp, isize)>,
+        answers: &mut TraceHandle<K, V>,
+        asks: Vec<K>,
+        worker: &mut Worker<A>,
+    ) -> Vec<Vec<V>> {
+        self.pose(input, &asks);
+        self.advance(worker);
+        self.collect(input, answers, asks)
+    }
+
+    /// The first half of `ask`, adding `asks` to the query input. Asks of
+    /// several kinds posed together are answered by a single `advance`.
+    fn pose<K: ExchangeData>(
+        &mut self,
+        input: fn(&mut Self) -> &mut Handle<Timestamp, (K, Timestamp, isize)>,
+        asks: &[K],
+    ) {
+        if self.leader {
+            let time = self.counter;
+            for ask in asks {
+                input(self).send((ask.clone(), time, 1));
+            }
+        }
+    }
+
+    /// The second half of `ask`, reading the answers to `asks` and
+    /// retracting them.
+    fn collect<K: ExchangeData, V: ExchangeData>(
+        &mut self,
+        input: fn(&mut Self) -> &mut Handle<Timestamp, (K, Timestamp, isize)>,
+        answers: &mut TraceHandle<K, V>,
+        asks: Vec<K>,
+    ) -> Vec<Vec<V>> {
+        self.compact(answers);
+        let answered = asks.iter().map(|ask| self.lookup(answers, ask)).collect();
+        if self.leader {
+            let time = self.counter;
+            for ask in asks {
+                input(self).send((ask, time, -1));
+            }
+        }
+        answered
+    }
+
+    /// The direct dependencies, or with `upstream` false the dependents, of
+    /// `name`.
+    fn query_edges<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        upstream: bool,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        self.compact(&mut traces.upstream);
+        self.compact(&mut traces.downstream);
+        let asks = vec![(upstream, name)];
+        let mut answered = self.ask(
+            |ctx| &mut ctx.lookup_input,
+            &mut traces.lookups,
+            asks,
+            worker,
+        );
+        answered.pop().unwrap_or_default()
+    }
+
+    /// The cascade of every ask, answered together.
+    fn query_cascades<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        asks: Vec<Ask<N>>,
+        worker: &mut Worker<A>,
+    ) -> Vec<HashMap<N, Vec<N>>> {
+        self.compact(&mut traces.upstream);
+        self.compact(&mut traces.downstream);
+        self.ask(
+            |ctx| &mut ctx.cascade_input,
+            &mut traces.cascades,
+            asks,
+            worker,
+        )
+        .into_iter()
+        .map(grouped)
+        .collect()
+    }
+
+    /// The union of the cascades of `names`, see `query_cascades`.
+    fn query_cascade_union<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        upstream: bool,
+        names: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let asks = names.into_iter().map(|name| (upstream, name)).collect();
+        let mut union = HashMap::new();
+        for cascade in self.query_cascades(traces, asks, worker) {
+            union.extend(cascade);
+        }
+        union
+    }
+
+    /// The edges within `k` hops of every ask, answered together.
+    fn query_hops<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        asks: Vec<HopAsk<N>>,
+        worker: &mut Worker<A>,
+    ) -> Vec<HashMap<N, Vec<N>>> {
+        self.compact(&mut traces.upstream);
+        self.compact(&mut traces.downstream);
+        self.ask(|ctx| &mut ctx.hop_input, &mut traces.hops, asks, worker)
+            .into_iter()
+            .map(grouped)
+            .collect()
+    }
+
+    fn delete<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Result<(), Error<N>> {
+        match self.config.dangling {
+            DanglingPolicy::KeepDangling => {}
+            DanglingPolicy::PruneEdges => {
+                let dependents = self.query_edges(traces, false, name.clone(), worker);
+                for (dependent, mut values) in
+                    self.query_many(&mut traces.upserted, dependents, worker)
+                {
+                    if let Some(dependencies) = values.pop() {
+                        let mut dependencies = dependencies.to_vec();
+                        dependencies.retain(|(d, _, _, _)| *d != name);
+                        let dependencies = Some(dependencies.into());
+                        self.write(dependent, dependencies);
+                    }
+                }
+            }
+            DanglingPolicy::Reject => {
+                let dependents = self.query_edges(traces, false, name.clone(), worker);
+                if dependents.iter().any(|d| *d != name) {
+                    return Err(Error::HasDependents(name));
+                }
+            }
+        }
+        self.write(name, None);
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_many<V: ExchangeData, A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, V>,
+        names: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<(N, Vec<V>)> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = names
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection()
+                .distinct();
+            let lineage = held(&trace.import(scope).semijoin(&query));
+
+            lineage.stream.probe_with(&mut self.probe);
+            lineage.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+    }
+
+    /// Moves the dependencies, metadata, type and tombstone of `old` to `new`
+    /// and repoints every dependent of `old`, all at the same timestamp. With
+    /// `merge` the existing dependencies and metadata of `new` are kept and
+    /// unioned with those of `old`, and its type and tombstone win, otherwise
+    /// they are overwritten.
+    fn rename<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        old: N,
+        new: N,
+        merge: bool,
+        worker: &mut Worker<A>,
+    ) {
+        if old == new {
+            return;
+        }
+
+        let dependencies = self.query(&mut traces.upserted, old.clone(), worker).pop();
+        let metadata = self.query(&mut traces.metadata, old.clone(), worker).pop();
+        let node_type = self.query(&mut traces.types, old.clone(), worker).pop();
+        let tombstone = self
+            .query(&mut traces.tombstones, old.clone(), worker)
+            .pop();
+        let dependents = self.query_edges(traces, false, old.clone(), worker);
+        let dependents = self.query_many(&mut traces.upserted, dependents, worker);
+        let (existing, existing_metadata, existing_type) = if merge {
+            (
+                self.query(&mut traces.upserted, new.clone(), worker).pop(),
+                self.query(&mut traces.metadata, new.clone(), worker).pop(),
+                self.query(&mut traces.types, new.clone(), worker).pop(),
+            )
+        } else {
+            (None, None, None)
+        };
+
+        let replace = |dependencies: &[Dependency<N>]| -> Vec<Dependency<N>> {
+            dependencies
+                .iter()
+                .map(|(d, kind, weight, valid)| {
+                    let d = if *d == old { new.clone() } else { d.clone() };
+                    (d, kind.clone(), *weight, *valid)
+                })
+                .collect()
+        };
+        for (name, mut values) in dependents {
+            if name == old || name == new {
+                continue;
+            }
+            if let Some(dependencies) = values.pop() {
+                self.write(name, Some(replace(&dependencies).into()));
+            }
+        }
+        if dependencies.is_some() || existing.is_some() || !merge {
+            let mut merged = replace(existing.as_deref().unwrap_or_default());
+            for (d, kind, weight, valid) in replace(dependencies.as_deref().unwrap_or_default()) {
+                if !merged.iter().any(|m| m.0 == d) {
+                    merged.push((d, kind, weight, valid));
+                }
+            }
+            if merge {
+                merged.retain(|(d, _, _, _)| *d != new);
+            }
+            if dependencies.is_some() {
+                self.write(old.clone(), None);
+            }
+            self.write(new.clone(), Some(merged.into()));
+        }
+        if let Some(metadata) = metadata {
+            let mut merged = existing_metadata.unwrap_or_default();
+            for (key, value) in metadata {
+                if !merged.iter().any(|m| m.0 == key) {
+                    merged.push((key, value));
+                }
+            }
+            merged.sort();
+            if self.leader {
+                self.meta_input.send((old.clone(), None, self.counter));
+                self.meta_input
+                    .send((new.clone(), Some(merged), self.counter));
+            }
+        }
+        if self.leader {
+            if node_type.is_some() {
+                self.type_input.send((old, None, self.counter));
+            }
+            if existing_type.is_none() {
+                self.type_input.send((new.clone(), node_type, self.counter));
+            }
+            if tombstone.is_some() {
+                self.tombstone_input.send((old, None, self.counter));
+            }
+            if !merge {
+                self.tombstone_input.send((new, tombstone, self.counter));
+            }
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        names: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let current = self.counter;
+        self.query_cascade_at(trace, names, current, worker)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_at<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        names: Vec<N>,
+        time: Timestamp,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let time = self.retained(time);
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = names
+                .to_leader_stream(scope)
+                .map(move |x| (x, time, 1))
+                .as_collection();
+            let res = held(&cascade(&trace.import(scope), &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read_at(&mut result_trace, time).into_iter().collect()
+    }
+
+    /// `query_cascade` against a maintained closure, see
+    /// `Config::maintain_closure`.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_closed<A: Allocate>(
+        &mut self,
+        closure: &mut TraceHandle<N>,
+        trace: &mut TraceHandle<N>,
+        names: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = names
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let reached = closure
+                .import(scope)
+                .semijoin(&query)
+                .map(|kv| kv.1)
+                .concat(&query)
+                .distinct();
+            let res = held(&trace.import(scope).semijoin(&reached));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(closure);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace).into_iter().collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_of_kind<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, (N, EdgeKind)>,
+        name: N,
+        kinds: Vec<EdgeKind>,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let lineage = held(&of_kind(&trace.import(scope), kinds).semijoin(&query));
+
+            lineage.stream.probe_with(&mut self.probe);
+            lineage.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read(&mut result_trace);
+        result.pop().map(|d| d.1).unwrap_or(vec![])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_of_kind<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, (N, EdgeKind)>,
+        name: N,
+        kinds: Vec<EdgeKind>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = of_kind(&trace.import(scope), kinds);
+            let res = held(&cascade(&arranged, &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace).into_iter().collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_closure<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res = held(&closure(&trace.import(scope), &query).map(|x| (x, ())));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_paths<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        from: N,
+        to: N,
+        max_depth: usize,
+        worker: &mut Worker<A>,
+    ) -> Vec<Vec<N>> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(from.clone())
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let mut frontier = query.map(|x| (x.clone(), vec![x]));
+            let mut paths = frontier.filter(|_| false).map(|(_, path)| path);
+            for _ in 0..max_depth {
+                frontier = frontier.join_core(&arranged, |_last, path, next| {
+                    if path.contains(next) {
+                        return None;
+                    }
+                    let mut path = path.clone();
+                    path.push(next.clone());
+                    Some((next.clone(), path))
+                });
+                let target = to.clone();
+                paths = frontier
+                    .filter(move |(last, _)| *last == target)
+                    .map(|(_, path)| path)
+                    .concat(&paths);
+                let target = to.clone();
+                frontier = frontier.filter(move |(last, _)| *last != target);
+            }
+            let res = held(&paths.map(move |path| (from.clone(), path)));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read(&mut result_trace);
+        result.pop().map(|d| d.1).unwrap_or(vec![])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_distance<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        from: N,
+        to: N,
+        worker: &mut Worker<A>,
+    ) -> Option<usize> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(from)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res =
+                held(&distances(&trace.import(scope), &query).filter(move |(node, _)| *node == to));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read(&mut result_trace);
+        result.pop().and_then(|d| d.1.into_iter().next())
+    }
+
+    /// Nodes that have no outgoing edges in `trace`, `reverse` being the
+    /// same edges in the opposite direction.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_boundary<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        trace: &mut TraceHandle<N>,
+        reverse: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let mut result_trace = worker.dataflow(|scope| {
+            let keys = trace
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .distinct();
+            let res = held(
+                &nodes
+                    .import(scope)
+                    .as_collection(|k, _| k.clone())
+                    .concat(&reverse.import(scope).as_collection(|k, _| k.clone()))
+                    .distinct()
+                    .map(|x| (x, ()))
+                    .antijoin(&keys),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(trace);
+        self.compact(reverse);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_topological_order<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        name: Option<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let downstream = downstream.import(scope);
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.as_collection(|k, _| k.clone()))
+                .distinct();
+            let mut levels = levels(&upstream, &downstream, &all);
+            if let Some(name) = name {
+                let query = Some(name)
+                    .to_leader_stream(scope)
+                    .map(move |x| (x, current, 1))
+                    .as_collection();
+                let reachable = closure(&upstream, &query).concat(&query).distinct();
+                levels = levels.semijoin(&reachable);
+            }
+            let res = held(&levels);
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        let mut result: Vec<(usize, N)> = self
+            .read(&mut result_trace)
+            .into_iter()
+            .filter_map(|(name, levels)| levels.into_iter().next().map(|l| (l, name)))
+            .collect();
+        result.sort();
+        result.into_iter().map(|d| d.1).collect()
+    }
+
+    fn has_cycle<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> bool {
+        let mut result_trace = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let downstream = downstream.import(scope);
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.as_collection(|k, _| k.clone()))
+                .distinct();
+            let levels = levels(&upstream, &downstream, &all);
+            let res = held(
+                &all.map(|x| (x, ()))
+                    .antijoin(&levels.map(|(x, _)| x))
+                    .map(|_| ((), ()))
+                    .distinct(),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        !self.read(&mut result_trace).is_empty()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_common<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        a: N,
+        b: N,
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query_a = Some(a)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let query_b = Some(b)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let res = held(
+                &closure(&arranged, &query_a)
+                    .map(|x| (x, ()))
+                    .semijoin(&closure(&arranged, &query_b)),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_diff<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        a: N,
+        b: N,
+        worker: &mut Worker<A>,
+    ) -> (HashSet<N>, HashSet<N>) {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query_a = Some(a)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let query_b = Some(b)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let closure_a = closure(&arranged, &query_a);
+            let closure_b = closure(&arranged, &query_b);
+            let only_a = closure_a
+                .map(|x| (x, ()))
+                .antijoin(&closure_b)
+                .map(|(x, ())| (true, x));
+            let only_b = closure_b
+                .map(|x| (x, ()))
+                .antijoin(&closure_a)
+                .map(|(x, ())| (false, x));
+            let res = held(&only_a.concat(&only_b));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut diff = (HashSet::new(), HashSet::new());
+        for (in_a, nodes) in self.read(&mut result_trace) {
+            if in_a {
+                diff.0.extend(nodes);
+            } else {
+                diff.1.extend(nodes);
+            }
+        }
+        diff
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_impact<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Impact<N> {
+        let asks = vec![(true, name.clone()), (false, name)];
+        let mut answered = self.query_cascades(traces, asks, worker);
+        Impact {
+            downstream: answered.pop().unwrap_or_default(),
+            upstream: answered.pop().unwrap_or_default(),
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_subgraph<A: Allocate>(
+        &mut self,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        sources: Vec<N>,
+        sinks: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<(N, N)> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let sources = sources
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let sinks = sinks
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let upstream = upstream.import(scope);
+            let downstream = downstream.import(scope);
+            let from_sources = closure(&downstream, &sources).concat(&sources).distinct();
+            let to_sinks = closure(&upstream, &sinks).concat(&sinks).distinct();
+            let on_path = from_sources
+                .map(|x| (x, ()))
+                .semijoin(&to_sinks)
+                .map(|(x, ())| x);
+            let res = held(
+                &upstream
+                    .semijoin(&on_path)
+                    .map(|(k, v)| (v, k))
+                    .semijoin(&on_path)
+                    .map(|(v, k)| (k, v)),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .flat_map(|(k, vs)| vs.into_iter().map(move |v| (k.clone(), v)))
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_count<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        cascade: bool,
+        worker: &mut Worker<A>,
+    ) -> usize {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name.clone())
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let reached = if cascade {
+                closure(&arranged, &query)
+            } else {
+                arranged.semijoin(&query).map(|kv| kv.1)
+            };
+            let res = held(&reached.map(move |_| name.clone()).count());
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read(&mut result_trace);
+        result
+            .pop()
+            .and_then(|d| d.1.into_iter().next())
+            .map(|count| count as usize)
+            .unwrap_or(0)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_top<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        n: usize,
+        worker: &mut Worker<A>,
+    ) -> Vec<(N, usize)> {
+        if n == 0 {
+            return vec![];
+        }
+
+        let mut result_trace = worker.dataflow(|scope| {
+            let res = held(
+                &trace
+                    .import(scope)
+                    .as_collection(|k, _| k.clone())
+                    .count()
+                    .map(|(name, count)| ((), (count, name)))
+                    .reduce(move |_key, input, output| {
+                        // busiest first, ties by name as the result is sorted
+                        let mut top: Vec<&(isize, N)> = input.iter().map(|(v, _)| *v).collect();
+                        top.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+                        for v in top.into_iter().take(n) {
+                            output.push((v.clone(), 1));
+                        }
+                    }),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result: Vec<(N, usize)> = self
+            .read(&mut result_trace)
+            .into_iter()
+            .flat_map(|(_, vs)| vs)
+            .map(|(count, name)| (name, count as usize))
+            .collect();
+        result.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        result
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_filtered<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        types: &mut TraceHandle<N, NodeType>,
+        name: N,
+        allowed: Vec<NodeType>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let allowed = types.import(scope).flat_map_ref(move |k, t| {
+                if allowed.contains(t) {
+                    Some(k.clone())
+                } else {
+                    None
+                }
+            });
+            let arranged = trace
+                .import(scope)
+                .as_collection(|k, v| (v.clone(), k.clone()))
+                .semijoin(&allowed)
+                .map(|(v, k)| (k, v))
+                .arrange_by_key();
+            let res = held(&cascade(&arranged, &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(types);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace).into_iter().collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_with_depth<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, (usize, Vec<N>)> {
+        let current = self.counter;
+        let (mut edges_trace, mut depth_trace) = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let edges = cascade(&arranged, &query);
+            let nodes = edges.flat_map(|(k, v)| vec![k, v]).distinct();
+            let depths = distances(&arranged, &query).semijoin(&nodes);
+
+            let edges = held(&edges);
+            let depths = held(&depths);
+            edges.stream.probe_with(&mut self.probe);
+            depths.stream.probe_with(&mut self.probe);
+            (edges.trace, depths.trace)
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut edges_trace);
+        self.compact(&mut depth_trace);
+        let mut edges: HashMap<N, Vec<N>> = self.read(&mut edges_trace).into_iter().collect();
+        self.read(&mut depth_trace)
+            .into_iter()
+            .filter_map(|(node, mut depth)| {
+                let dependencies = edges.remove(&node).unwrap_or_default();
+                depth.pop().map(|depth| (node, (depth, dependencies)))
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_stream<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        batch_size: usize,
+        tx: Sender<Vec<(N, Vec<N>)>>,
+        worker: &mut Worker<A>,
+    ) {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res = held(&cascade(&trace.import(scope), &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let batch_size = batch_size.max(1);
+        let mut batch = Vec::with_capacity(batch_size);
+        let replies = self.replies;
+        self.read_with(&mut result_trace, self.counter, |key, values| {
+            batch.push((key, values));
+            if batch.len() < batch_size {
+                return true;
+            }
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            !replies || tx.send(full).is_ok()
+        });
+        if replies && !batch.is_empty() {
+            let _ = tx.send(batch);
+        }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_at_depth<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        depth: usize,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name.clone())
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res = held(
+                &distances(&trace.import(scope), &query)
+                    .filter(move |(_, d)| *d == depth)
+                    .map(move |(node, _)| (name.clone(), node)),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read(&mut result_trace);
+        result.pop().map(|d| d.1).unwrap_or(vec![])
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_until<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        metadata: &mut TraceHandle<N, MetaVal>,
+        name: N,
+        stop: Stop<N>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let mut stops = stop
+                .nodes
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            if let Some(predicate) = stop.metadata {
+                let tagged = metadata.import(scope).flat_map_ref(move |k, v| {
+                    let m: Metadata = v.iter().cloned().collect();
+                    if predicate(&m) {
+                        Some(k.clone())
+                    } else {
+                        None
+                    }
+                });
+                stops = stops.concat(&tagged);
+            }
+            let res = held(&cascade_until(
+                &trace.import(scope),
+                &query,
+                &stops.distinct(),
+            ));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(metadata);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace).into_iter().collect()
+    }
+
+    /// The cascade cut after the deepest complete level that keeps it within
+    /// `max_nodes` reached nodes.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_bounded<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        max_nodes: usize,
+        worker: &mut Worker<A>,
+    ) -> Bounded<HashMap<N, Vec<N>>> {
+        let current = self.counter;
+        let (mut edges_trace, mut depth_trace) = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let depths = distances_until(&arranged, &query, max_nodes);
+            let edges = arranged.semijoin(&depths.map(|(node, _)| node));
+
+            let edges = held(&edges);
+            let depths = held(&depths);
+            edges.stream.probe_with(&mut self.probe);
+            depths.stream.probe_with(&mut self.probe);
+            (edges.trace, depths.trace)
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut edges_trace);
+        self.compact(&mut depth_trace);
+        let mut depths: Vec<(usize, N)> = self
+            .read(&mut depth_trace)
+            .into_iter()
+            .filter_map(|(node, mut depth)| depth.pop().map(|d| (d, node)))
+            .collect();
+        depths.sort();
+        // the count the exploration stopped at
+        let reached = depths.iter().filter(|(d, _)| *d > 0).count();
+        let truncated = reached > max_nodes;
+        let mut included = HashSet::new();
+        let mut count = 0;
+        let mut i = 0;
+        while i < depths.len() {
+            let depth = depths[i].0;
+            let level = depths[i..].iter().take_while(|(d, _)| *d == depth).count();
+            if depth > 0 && count + level > max_nodes {
+                break;
+            }
+            if depth > 0 {
+                count += level;
+            }
+            included.extend(depths[i..i + level].iter().map(|(_, node)| node.clone()));
+            i += level;
+        }
+        let result = self
+            .read(&mut edges_trace)
+            .into_iter()
+            .filter(|(k, _)| included.contains(k))
+            .map(|(k, mut vs)| {
+                vs.retain(|v| included.contains(v));
+                (k, vs)
+            })
+            .filter(|(_, vs)| !vs.is_empty())
+            .collect();
+        Bounded { result, truncated }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cheapest_path<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, (N, Weight)>,
+        from: N,
+        to: N,
+        worker: &mut Worker<A>,
+    ) -> Option<(Weight, Vec<N>)> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(from)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let weighted = trace.import(scope);
+            let roots = query.map(|x| (x.clone(), (0, vec![x])));
+            let res = held(
+                &roots
+                    .iterate(|best| {
+                        let weighted = weighted.enter(&best.scope());
+                        let roots = roots.enter(&best.scope());
+                        best.join_core(&weighted, |_node, (cost, path), (next, weight)| {
+                            if path.contains(next) {
+                                return None;
+                            }
+                            let mut path = path.clone();
+                            path.push(next.clone());
+                            Some((next.clone(), (cost.saturating_add(*weight), path)))
+                        })
+                        .concat(&roots)
+                        .reduce(|_node, input, output| output.push(((*input[0].0).clone(), 1)))
+                    })
+                    .filter(move |(node, _)| *node == to),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut result = self.read(&mut result_trace);
+        result.pop().and_then(|d| d.1.into_iter().next())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_component<A: Allocate>(
+        &mut self,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let undirected = upstream
+                .import(scope)
+                .as_collection(|k, v| (k.clone(), v.clone()))
+                .concat(
+                    &downstream
+                        .import(scope)
+                        .as_collection(|k, v| (k.clone(), v.clone())),
+                )
+                .arrange_by_key();
+            let res = held(
+                &closure(&undirected, &query)
+                    .concat(&query)
+                    .distinct()
+                    .map(|x| (x, ())),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_components<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<HashSet<N>> {
+        let mut result_trace = worker.dataflow(|scope| {
+            let downstream = downstream.import(scope);
+            let undirected = upstream
+                .import(scope)
+                .as_collection(|k, v| (k.clone(), v.clone()))
+                .concat(&downstream.as_collection(|k, v| (k.clone(), v.clone())))
+                .arrange_by_key();
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.as_collection(|k, _| k.clone()))
+                .distinct();
+            let res = held(&components(&undirected, &all).map(|(node, label)| (label, node)));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|(_, members)| members.into_iter().collect())
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_strong_components<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<HashSet<N>> {
+        let mut result_trace = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.import(scope).as_collection(|k, _| k.clone()))
+                .distinct();
+            let res = held(&strong_components(&upstream, &all).map(|(node, label)| (label, node)));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|(_, members)| members.into_iter().collect())
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_condensation<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Condensation<N> {
+        let (mut labels_trace, mut edges_trace) = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.import(scope).as_collection(|k, _| k.clone()))
+                .distinct();
+            let labels = strong_components(&upstream, &all);
+            let edges = held(
+                &upstream
+                    .as_collection(|k, v| (k.clone(), v.clone()))
+                    .join_map(&labels, |_from, to, from_label| {
+                        (to.clone(), from_label.clone())
+                    })
+                    .join_map(&labels, |_to, from_label, to_label| {
+                        (from_label.clone(), to_label.clone())
+                    })
+                    .filter(|(from, to)| from != to)
+                    .distinct(),
+            );
+            let labels = held(&labels.map(|(node, label)| (label, node)));
+
+            labels.stream.probe_with(&mut self.probe);
+            edges.stream.probe_with(&mut self.probe);
+            (labels.trace, edges.trace)
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut labels_trace);
+        self.compact(&mut edges_trace);
+        let mut index = HashMap::new();
+        let mut components = vec![];
+        for (label, members) in self.read(&mut labels_trace) {
+            index.insert(label, components.len());
+            components.push(members.into_iter().collect());
+        }
+        let edges = self
+            .read(&mut edges_trace)
+            .into_iter()
+            .flat_map(|(from, tos)| {
+                let from = index[&from];
+                tos.into_iter()
+                    .map(|to| (from, index[&to]))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Condensation { components, edges }
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_transitive_reduction<A: Allocate>(
+        &mut self,
+        upstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<(N, N)> {
+        let mut result_trace = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let reach = reachability(&upstream);
+            let implied = upstream
+                .as_collection(|k, v| (v.clone(), k.clone()))
+                .join_map(&reach, |_via, from, to| (from.clone(), to.clone()))
+                .distinct();
+            let res = held(
+                &upstream
+                    .as_collection(|k, v| ((k.clone(), v.clone()), ()))
+                    .antijoin(&implied)
+                    .map(|(edge, ())| edge),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(upstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .flat_map(|(k, vs)| vs.into_iter().map(move |v| (k.clone(), v)))
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_siblings<A: Allocate>(
+        &mut self,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name.clone())
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let dependencies = upstream.import(scope).semijoin(&query).map(|kv| kv.1);
+            let res = held(
+                &downstream
+                    .import(scope)
+                    .semijoin(&dependencies)
+                    .map(|kv| kv.1)
+                    .filter(move |x| *x != name)
+                    .distinct()
+                    .map(|x| (x, ())),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_orphans<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let mut result_trace = worker.dataflow(|scope| {
+            let connected = upstream
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.import(scope).as_collection(|k, _| k.clone()))
+                .distinct();
+            let res = held(
+                &nodes
+                    .import(scope)
+                    .as_collection(|k, _| (k.clone(), ()))
+                    .antijoin(&connected),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    /// Whether `to` can be reached from `from`, walking the arrangement one
+    /// hop at a time and stopping as soon as `to` is found.
+    fn reaches<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        from: N,
+        to: N,
+        worker: &mut Worker<A>,
+    ) -> bool {
+        let mut seen = HashSet::new();
+        let mut hop = vec![from];
+        while !hop.is_empty() {
+            let mut next_hop = vec![];
+            for (_, reached) in self.query_many(trace, hop, worker) {
+                for next in reached {
+                    if next == to {
+                        return true;
+                    }
+                    if seen.insert(next.clone()) {
+                        next_hop.push(next);
+                    }
+                }
+            }
+            hop = next_hop;
+        }
+        false
+    }
+
+    /// `reaches` against a maintained closure, see `Config::maintain_closure`.
+    fn reaches_closed<A: Allocate>(
+        &mut self,
+        closure: &mut TraceHandle<N>,
+        from: N,
+        to: N,
+        worker: &mut Worker<A>,
+    ) -> bool {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(from)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res = held(
+                &closure
+                    .import(scope)
+                    .semijoin(&query)
+                    .filter(move |(_, reached)| *reached == to),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(closure);
+        self.compact(&mut result_trace);
+        !self.read(&mut result_trace).is_empty()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_lowest_common<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        a: N,
+        b: N,
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query_a = Some(a)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let query_b = Some(b)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let common = closure(&arranged, &query_a)
+                .map(|x| (x, ()))
+                .semijoin(&closure(&arranged, &query_b));
+            let below = closure(&arranged, &common.map(|(x, ())| x));
+            let res = held(&common.antijoin(&below));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    /// The level of `name` and of every node it depends on, see `levels`.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_levels<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, usize> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let downstream = downstream.import(scope);
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let reachable = closure(&upstream, &query).concat(&query).distinct();
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.as_collection(|k, _| k.clone()))
+                .distinct();
+            let res = held(&levels(&upstream, &downstream, &all).semijoin(&reachable));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .filter_map(|(name, levels)| levels.into_iter().next().map(|l| (name, l)))
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_critical_path<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Option<Vec<N>> {
+        let levels = self.query_levels(nodes, upstream, downstream, name.clone(), worker);
+        let mut level = *levels.get(&name)?;
+        let mut path = vec![name];
+        while level > 0 {
+            let last = path[path.len() - 1].clone();
+            let next = self
+                .query(upstream, last, worker)
+                .into_iter()
+                .find(|dep| levels.get(dep) == Some(&(level - 1)))
+                .expect("a dependency one level below");
+            path.push(next);
+            level -= 1;
+        }
+        Some(path)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_stats<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        upstream: &mut TraceHandle<N>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Stats {
+        let mut result_trace = worker.dataflow(|scope| {
+            let upstream = upstream.import(scope);
+            let downstream = downstream.import(scope);
+            let all = nodes
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .concat(&downstream.as_collection(|k, _| k.clone()))
+                .distinct();
+            let max_degree = |degrees: Collection<_, (N, isize)>, tag: u8| {
+                degrees
+                    .map(move |(_, degree)| (tag, degree))
+                    .reduce(|_tag, input, output| output.push((*input[input.len() - 1].0, 1)))
+            };
+            let keys = |arranged: &Arranged<_, TraceHandle<N>>| {
+                arranged.as_collection(|k, _| k.clone()).distinct()
+            };
+            // tags: nodes, edges, max in degree, max out degree, roots,
+            // leaves, nodes without a level
+            let res = held(
+                &all.map(|_| 0u8)
+                    .concat(&upstream.as_collection(|_, _| 1u8))
+                    .concat(&all.map(|x| (x, ())).antijoin(&keys(&upstream)).map(|_| 4u8))
+                    .concat(
+                        &all.map(|x| (x, ()))
+                            .antijoin(&keys(&downstream))
+                            .map(|_| 5u8),
+                    )
+                    .concat(
+                        &all.map(|x| (x, ()))
+                            .antijoin(&levels(&upstream, &downstream, &all).map(|(x, _)| x))
+                            .map(|_| 6u8),
+                    )
+                    .count()
+                    .concat(&max_degree(
+                        downstream.as_collection(|k, _| k.clone()).count(),
+                        2,
+                    ))
+                    .concat(&max_degree(
+                        upstream.as_collection(|k, _| k.clone()).count(),
+                        3,
+                    )),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(upstream);
+        self.compact(downstream);
+        self.compact(&mut result_trace);
+        let mut stats = Stats::default();
+        for (tag, values) in self.read(&mut result_trace) {
+            let value = values.into_iter().next().unwrap_or(0) as usize;
+            match tag {
+                0 => stats.nodes = value,
+                1 => stats.edges = value,
+                2 => stats.max_in_degree = value,
+                3 => stats.max_out_degree = value,
+                4 => stats.roots = value,
+                5 => stats.leaves = value,
+                _ => stats.has_cycle = value > 0,
+            }
+        }
+        stats
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_critical_nodes<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        sink: N,
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        let cascade = self.query_cascade(trace, vec![sink.clone()], worker);
+        let reaches_root = |removed: &N| {
+            let mut seen = HashSet::new();
+            let mut stack = vec![&sink];
+            while let Some(node) = stack.pop() {
+                match cascade.get(node) {
+                    None => return true,
+                    Some(deps) => stack.extend(
+                        deps.iter()
+                            .filter(|dep| *dep != removed && seen.insert(*dep)),
+                    ),
+                }
+            }
+            false
+        };
+        if !cascade.contains_key(&sink) || !reaches_root(&sink) {
+            return HashSet::new();
+        }
+        cascade
+            .values()
+            .flatten()
+            .filter(|node| !reaches_root(node))
+            .cloned()
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_shared<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        mut names: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> HashSet<N> {
+        names.sort();
+        names.dedup();
+        let sources = names.len() as isize;
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = names
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let arranged = trace.import(scope);
+            let first = query
+                .map(|x| (x.clone(), x))
+                .join_core(&arranged, |_name, source, dep| {
+                    Some((dep.clone(), source.clone()))
+                })
+                .distinct();
+            let reached = first.iterate(|reach| {
+                let arranged = arranged.enter(&reach.scope());
+                reach
+                    .join_core(&arranged, |_name, source, dep| {
+                        Some((dep.clone(), source.clone()))
+                    })
+                    .concat(&first.enter(&reach.scope()))
+                    .distinct()
+            });
+            let res = held(
+                &reached
+                    .map(|(node, _source)| node)
+                    .count()
+                    .filter(move |(_, count)| *count == sources)
+                    .map(|(node, _)| (node, ())),
+            );
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_excluding<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        exclude: Vec<N>,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let current = self.counter;
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let exclude = exclude
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection()
+                .distinct();
+            let remaining = trace
+                .import(scope)
+                .as_collection(|k, v| (v.clone(), k.clone()))
+                .antijoin(&exclude)
+                .map(|(v, k)| (k, v))
+                .antijoin(&exclude)
+                .arrange_by_key();
+            let res = held(&cascade(&remaining, &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace).into_iter().collect()
+    }
+
+    /// The edges of the cascade of `name` that changed between `from` and
+    /// `to`, both read from the one result trace.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_diff_at<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        from: Timestamp,
+        to: Timestamp,
+        worker: &mut Worker<A>,
+    ) -> EdgeDiff<N> {
+        let from = self.retained(from);
+        let to = self.retained(to);
+        let start = from.min(to);
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, start, 1))
+                .as_collection();
+            let res = held(&cascade(&trace.import(scope), &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        let mut edges_at = |time| -> HashSet<(N, N)> {
+            self.read_at(&mut result_trace, time)
+                .into_iter()
+                .flat_map(|(k, vs)| vs.into_iter().map(move |v| (k.clone(), v)))
+                .collect()
+        };
+        let before = edges_at(from);
+        let after = edges_at(to);
+        EdgeDiff {
+            added: after.difference(&before).cloned().collect(),
+            removed: before.difference(&after).cloned().collect(),
+        }
+    }
+
+    /// Installs a cascade dataflow that stays in place and sends every
+    /// consolidated change of its edges to `tx`, starting with the current
+    /// edges as additions.
+    fn subscribe_cascade<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        tx: Sender<ChangeBatch<N>>,
+        worker: &mut Worker<A>,
+    ) {
+        let current = self.counter;
+        let index = worker.next_dataflow_index();
+        self.kept.insert(index);
+        worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let replies = self.replies;
+            let abandoned = self.abandoned.clone();
+            let mut listening = true;
+            cascade(&trace.import(scope), &query)
+                .consolidate()
+                .inner
+                .broadcast()
+                .inspect_batch(move |_time, changes| {
+                    if replies && listening && tx.send(changes.to_vec()).is_err() {
+                        listening = false;
+                        abandoned.borrow_mut().push(index);
+                    }
+                })
+                .probe_with(&mut self.probe);
+        });
+
+        self.subscriptions.set(self.subscriptions.get() + 1);
+        self.advance(worker);
+        self.compact(trace);
+    }
+
+    /// Like `subscribe_cascade`, for every edge of the graph, sent one
+    /// change at a time.
+    fn subscribe_edges<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        tx: Sender<(N, N, isize, Timestamp)>,
+        worker: &mut Worker<A>,
+    ) {
+        let replies = self.replies;
+        let abandoned = self.abandoned.clone();
+        let mut listening = true;
+        let index = worker.next_dataflow_index();
+        self.kept.insert(index);
+        worker.dataflow(|scope| {
+            trace
+                .import(scope)
+                .as_collection(|k, v| (k.clone(), v.clone()))
+                .inner
+                .broadcast()
+                .inspect(move |((name, dependency), time, diff)| {
+                    if !replies || !listening {
+                        return;
+                    }
+                    let change = (name.clone(), dependency.clone(), *diff, *time);
+                    if tx.send(change).is_err() {
+                        listening = false;
+                        abandoned.borrow_mut().push(index);
+                    }
+                })
+                .probe_with(&mut self.probe);
+        });
+
+        self.subscriptions.set(self.subscriptions.get() + 1);
+        self.advance(worker);
+        self.compact(trace);
+    }
+
+    /// Installs a cascade dataflow that stays in place, its result kept up
+    /// to date for `poll_query` and pushed to `subscribe_query` receivers.
+    fn register_query<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> QueryHandle {
+        let current = self.counter;
+        let subscribers: Subscribers<N> = Rc::new(RefCell::new(vec![]));
+        let index = worker.next_dataflow_index();
+        self.kept.insert(index);
+        let result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let res = held(&cascade(&trace.import(scope), &query));
+            let notify = subscribers.clone();
+            let subscriptions = self.subscriptions.clone();
+            let replies = self.replies;
+            // held by every worker already, one of which notifies
+            res.as_collection(|k, v| (k.clone(), v.clone()))
+                .inner
+                .inspect_batch(move |_time, changes| {
+                    if replies {
+                        // a receiver dropped since the last changes no
+                        // longer keeps time advancing
+                        notify.borrow_mut().retain(|tx| {
+                            let listening = tx.send(changes.to_vec()).is_ok();
+                            if !listening {
+                                subscriptions.set(subscriptions.get() - 1);
+                            }
+                            listening
+                        });
+                    }
+                })
+                .probe_with(&mut self.probe);
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.standing.push(Some((result_trace, subscribers, index)));
+        QueryHandle(self.standing.len() - 1)
+    }
+
+    fn poll_query<A: Allocate>(
+        &mut self,
+        handle: QueryHandle,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        self.advance(worker);
+        let mut standing = std::mem::take(&mut self.standing);
+        let mut result = HashMap::new();
+        if let Some((result_trace, _, _)) = standing.get_mut(handle.0).and_then(Option::as_mut) {
+            self.compact(result_trace);
+            result = self.read(result_trace).into_iter().collect();
+        }
+        self.standing = standing;
+        result
+    }
+
+    /// Sends the current result of a standing query to `tx` as additions,
+    /// followed by every later change. `tx` is dropped for an unknown
+    /// handle.
+    fn subscribe_query(&mut self, handle: QueryHandle, tx: Sender<ChangeBatch<N>>) {
+        let mut standing = std::mem::take(&mut self.standing);
+        let Some((result_trace, subscribers, _)) =
+            standing.get_mut(handle.0).and_then(Option::as_mut)
+        else {
+            self.standing = standing;
+            return;
+        };
+        let time = self.counter;
+        let snapshot = self
+            .read(result_trace)
+            .into_iter()
+            .flat_map(|(k, vs)| vs.into_iter().map(move |v| ((k.clone(), v), time, 1)))
+            .collect();
+        if !self.replies || tx.send(snapshot).is_ok() {
+            subscribers.borrow_mut().push(tx);
+            self.subscriptions.set(self.subscriptions.get() + 1);
+        }
+        self.standing = standing;
+    }
+
+    /// Drops the dataflow of a standing query, and with it the senders of
+    /// its subscribers. Every worker handles the release, so unlike
+    /// `release` it is dropped throughout a cluster.
+    fn release_query<A: Allocate>(&mut self, handle: QueryHandle, worker: &mut Worker<A>) {
+        let Some((result_trace, subscribers, index)) =
+            self.standing.get_mut(handle.0).and_then(Option::take)
+        else {
+            return;
+        };
+        let listening = subscribers.borrow().len();
+        self.subscriptions.set(self.subscriptions.get() - listening);
+        self.kept.remove(&index);
+        drop(result_trace);
+        worker.drop_dataflow(index);
+    }
+
+    /// The times at which the `(from, to)` edge appeared (`1`) and
+    /// disappeared (`-1`), read off the `history` of `from`.
+    fn edge_history<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        from: N,
+        to: N,
+        worker: &mut Worker<A>,
+    ) -> Vec<(Timestamp, isize)> {
+        let mut history = vec![];
+        let mut trace = self.history(trace, from.clone(), worker);
+        let (mut cursor, storage) = trace.cursor();
+        cursor.seek_key(&storage, &from);
+        if cursor.key_valid(&storage) && *cursor.key(&storage) == from {
+            cursor.seek_val(&storage, &to);
+            if cursor.val_valid(&storage) && *cursor.val(&storage) == to {
+                cursor.map_times(&storage, |time, diff| history.push((*time, *diff)));
+            }
+        }
+        differential_dataflow::consolidation::consolidate(&mut history);
+        history
+    }
+
+    /// The latest time at which the dependency list of `name` changed.
+    fn last_modified<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, Dependencies<N>>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> Option<Timestamp> {
+        let mut last = None;
+        let mut trace = self.history(trace, name.clone(), worker);
+        let (mut cursor, storage) = trace.cursor();
+        cursor.seek_key(&storage, &name);
+        if !cursor.key_valid(&storage) || *cursor.key(&storage) != name {
+            return None;
+        }
+        while cursor.val_valid(&storage) {
+            cursor.map_times(&storage, |time, _| last = last.max(Some(*time)));
+            cursor.step_val(&storage);
+        }
+        last
+    }
+
+    /// Every node whose dependency list changed at or after `since`.
+    fn changed_since<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, Dependencies<N>>,
+        since: Timestamp,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        let mut result_trace = worker.dataflow(|scope| {
+            // each worker picks the changes out of its own shard, so that
+            // only their names are exchanged
+            let changed = trace
+                .import(scope)
+                .as_collection(|k, _| k.clone())
+                .inner
+                .flat_map(move |(name, time, _)| (time >= since).then_some((name, time, 1)))
+                .as_collection()
+                .distinct()
+                .map(|name| (name, ()));
+            let res = held(&changed);
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read(&mut result_trace)
+            .into_iter()
+            .map(|d| d.0)
+            .collect()
+    }
+
+    /// Every update of `name` in `trace` at the time it happened, rather
+    /// than as of one time like `query`.
+    fn history<V: ExchangeData, A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, V>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> TraceHandle<N, V> {
+        let mut result_trace = worker.dataflow(|scope| {
+            // asked at the very first time, so that every update is joined
+            // at its own
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(|x| (x, 0, 1))
+                .as_collection();
+            let res = held(&trace.import(scope).semijoin(&query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        result_trace
+    }
+
+    /// The cascade over the edges valid at `valid`, as recorded at `time`.
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_valid<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, (N, Validity)>,
+        name: N,
+        valid: ValidTime,
+        time: Timestamp,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        let time = self.retained(time);
+        let mut result_trace = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, time, 1))
+                .as_collection();
+            let edges = trace
+                .import(scope)
+                .flat_map_ref(move |k, (v, (start, end))| {
+                    if *start <= valid && valid < *end {
+                        Some((k.clone(), v.clone()))
+                    } else {
+                        None
+                    }
+                })
+                .arrange_by_key();
+            let res = held(&cascade(&edges, &query));
+
+            res.stream.probe_with(&mut self.probe);
+            res.trace
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(&mut result_trace);
+        self.read_at(&mut result_trace, time).into_iter().collect()
+    }
+
+    /// Compacts every trace, standing query results included, up to the
+    /// retained history and lets the spines merge what that frees up.
+    fn compact_all<A: Allocate>(&mut self, traces: &mut Traces<N>, worker: &mut Worker<A>) {
+        self.compact(&mut traces.upserted);
+        self.compact(&mut traces.nodes);
+        self.compact(&mut traces.upstream);
+        self.compact(&mut traces.downstream);
+        self.compact(&mut traces.upstream_typed);
+        self.compact(&mut traces.downstream_typed);
+        self.compact(&mut traces.upstream_weighted);
+        self.compact(&mut traces.upstream_valid);
+        self.compact(&mut traces.metadata);
+        self.compact(&mut traces.types);
+        self.compact(&mut traces.tombstones);
+        self.compact(&mut traces.audit);
+        self.compact(&mut traces.lookups);
+        self.compact(&mut traces.cascades);
+        self.compact(&mut traces.hops);
+        if let Some(trace) = traces.upstream_closure.as_mut() {
+            self.compact(trace);
+        }
+        if let Some(trace) = traces.downstream_closure.as_mut() {
+            self.compact(trace);
+        }
+        let mut standing = std::mem::take(&mut self.standing);
+        for (result_trace, _, _) in standing.iter_mut().flatten() {
+            self.compact(result_trace);
+        }
+        self.standing = standing;
+        self.advance(worker);
+    }
+
+    fn compaction_stats(&mut self, traces: &mut Traces<N>) -> CompactionStats {
+        let mut stats = CompactionStats::default();
+        tally(&mut traces.upserted, &mut stats);
+        tally(&mut traces.nodes, &mut stats);
+        tally(&mut traces.upstream, &mut stats);
+        tally(&mut traces.downstream, &mut stats);
+        tally(&mut traces.upstream_typed, &mut stats);
+        tally(&mut traces.downstream_typed, &mut stats);
+        tally(&mut traces.upstream_weighted, &mut stats);
+        tally(&mut traces.upstream_valid, &mut stats);
+        tally(&mut traces.metadata, &mut stats);
+        tally(&mut traces.types, &mut stats);
+        tally(&mut traces.tombstones, &mut stats);
+        tally(&mut traces.audit, &mut stats);
+        tally(&mut traces.lookups, &mut stats);
+        tally(&mut traces.cascades, &mut stats);
+        tally(&mut traces.hops, &mut stats);
+        if let Some(trace) = traces.upstream_closure.as_mut() {
+            tally(trace, &mut stats);
+        }
+        if let Some(trace) = traces.downstream_closure.as_mut() {
+            tally(trace, &mut stats);
+        }
+        for (result_trace, _, _) in self.standing.iter_mut().flatten() {
+            tally(result_trace, &mut stats);
+        }
+        stats
+    }
+
+    fn save_snapshot<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, Dependencies<N>>,
+        path: &Path,
+        worker: &mut Worker<A>,
+    ) -> io::Result<()> {
+        self.advance(worker);
+        self.compact(trace);
+        let mut trace = self.gather(trace, worker);
+        let nodes: Vec<(N, Dependencies<N>)> = self
+            .read(&mut trace)
+            .into_iter()
+            .filter_map(|(k, mut vs)| Some((k, vs.pop()?)))
+            .collect();
+        // every worker read the same, one per process writes it
+        if !self.replies {
+            return Ok(());
+        }
+        let mut bytes = records::header();
+        for node in &nodes {
+            records::append(node, &mut bytes);
+        }
+        std::fs::write(path, bytes)?;
+        match self.config.wal.clone().filter(|_| self.wal.is_some()) {
+            Some(wal) => self.rewrite_wal(&wal, &nodes),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes GraphML straight from the cursors of the traces, only
+    /// remembering which names were seen so that nodes referenced as a
+    /// dependency alone are declared as well.
+    fn export_graphml<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        id: &dyn Fn(&N) -> String,
+        worker: &mut Worker<A>,
+    ) -> String {
+        self.advance(worker);
+        self.compact(&mut traces.nodes);
+        self.compact(&mut traces.types);
+        self.compact(&mut traces.metadata);
+
+        let mut metadata = self.gather(&mut traces.metadata, worker);
+        let mut keys = BTreeMap::new();
+        let time = self.counter;
+        self.read_with(&mut metadata, time, |_, values| {
+            for (name, _) in values.into_iter().flatten() {
+                keys.entry(name).or_insert(0);
+            }
+            true
+        });
+        for (i, index) in keys.values_mut().enumerate() {
+            *index = i;
+        }
+
+        let mut out = export::graphml_header(&keys);
+        let mut declared = HashSet::new();
+        let mut referenced = HashSet::new();
+        let mut nodes = self.gather(&mut traces.nodes, worker);
+        let mut types = self.gather(&mut traces.types, worker);
+        self.scan(&mut nodes, time, |name, values| {
+            let node_type = self.lookup(&mut types, name).pop();
+            let node_metadata = self.lookup(&mut metadata, name).pop().unwrap_or_default();
+            let name_id = id(name);
+            out += &export::graphml_node(&name_id, node_type.as_ref(), &node_metadata, &keys);
+            for (d, kind, weight, _) in values.last().into_iter().flat_map(|v| v.iter()) {
+                out += &export::graphml_edge(&id(d), &name_id, kind, *weight);
+                referenced.insert(d.clone());
+            }
+            declared.insert(name.clone());
+            true
+        });
+        for name in referenced.difference(&declared) {
+            out += &export::graphml_node(&id(name), None, &[], &keys);
+        }
+        out += export::GRAPHML_FOOTER;
+        out
+    }
+
+    /// Writes GEXF straight from the cursors of the traces in two passes,
+    /// as GEXF wants every node before the first edge. Only the names seen
+    /// are remembered, see `export_graphml`.
+    fn export_gexf<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        id: &dyn Fn(&N) -> String,
+        worker: &mut Worker<A>,
+    ) -> String {
+        self.advance(worker);
+        self.compact(&mut traces.nodes);
+        self.compact(&mut traces.downstream);
+        self.compact(&mut traces.types);
+
+        let mut out = export::GEXF_HEADER.to_string();
+        let mut declared = HashSet::new();
+        let mut referenced = HashSet::new();
+        let mut nodes = self.gather(&mut traces.nodes, worker);
+        let mut downstream = self.gather(&mut traces.downstream, worker);
+        let mut types = self.gather(&mut traces.types, worker);
+        self.scan(&mut nodes, self.counter, |name, values| {
+            let dependencies = values.last().map_or(&[][..], |v| &v[..]);
+            let node_type = self.lookup(&mut types, name).pop();
+            let in_degree = self.lookup(&mut downstream, name).len();
+            out += &export::gexf_node(&id(name), node_type.as_ref(), in_degree, dependencies.len());
+            referenced.extend(dependencies.iter().map(|d| d.0.clone()));
+            declared.insert(name.clone());
+            true
+        });
+        for name in referenced.difference(&declared) {
+            let in_degree = self.lookup(&mut downstream, name).len();
+            out += &export::gexf_node(&id(name), None, in_degree, 0);
+        }
+
+        out += export::GEXF_EDGES;
+        let mut n = 0;
+        self.scan(&mut nodes, self.counter, |name, values| {
+            let name_id = id(name);
+            for (d, _, weight, _) in values.last().into_iter().flat_map(|v| v.iter()) {
+                out += &export::gexf_edge(n, &id(d), &name_id, *weight);
+                n += 1;
+            }
+            true
+        });
+        out += export::GEXF_FOOTER;
+        out
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_cascade_with_metadata<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        metadata: &mut TraceHandle<N, MetaVal>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> (HashMap<N, Vec<N>>, HashMap<N, Metadata>) {
+        let current = self.counter;
+        let (mut edges_trace, mut meta_trace) = worker.dataflow(|scope| {
+            let query = Some(name)
+                .to_leader_stream(scope)
+                .map(move |x| (x, current, 1))
+                .as_collection();
+            let edges = cascade(&trace.import(scope), &query);
+            let nodes = edges
+                .flat_map(|(k, v)| vec![k, v])
+                .concat(&query)
+                .distinct();
+            let meta = metadata.import(scope).semijoin(&nodes);
+
+            let edges = held(&edges);
+            let meta = held(&meta);
+            edges.stream.probe_with(&mut self.probe);
+            meta.stream.probe_with(&mut self.probe);
+            (edges.trace, meta.trace)
+        });
+
+        self.advance(worker);
+        self.compact(trace);
+        self.compact(metadata);
+        self.compact(&mut edges_trace);
+        self.compact(&mut meta_trace);
+        let edges = self.read(&mut edges_trace).into_iter().collect();
+        let meta = self
+            .read(&mut meta_trace)
+            .into_iter()
+            .filter_map(|(k, mut vs)| vs.pop().map(|v| (k, v.into_iter().collect())))
+            .collect();
+        (edges, meta)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_k<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        upstream: bool,
+        name: N,
+        k: usize,
+        worker: &mut Worker<A>,
+    ) -> HashMap<N, Vec<N>> {
+        if k == 0 {
+            return HashMap::new();
+        }
+        let asks = vec![(upstream, name, k)];
+        self.query_hops(traces, asks, worker)
+            .pop()
+            .unwrap_or_default()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    fn query_neighborhood<A: Allocate>(
+        &mut self,
+        traces: &mut Traces<N>,
+        name: N,
+        k: usize,
+        worker: &mut Worker<A>,
+    ) -> Impact<N> {
+        if k == 0 {
+            return Impact {
+                upstream: HashMap::new(),
+                downstream: HashMap::new(),
+            };
+        }
+        let asks = vec![(true, name.clone(), k), (false, name, k)];
+        let mut answered = self.query_hops(traces, asks, worker);
+        Impact {
+            downstream: answered.pop().unwrap_or_default(),
+            upstream: answered.pop().unwrap_or_default(),
+        }
+    }
+
+    fn exists<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N, Dependencies<N>>,
+        name: N,
+        worker: &mut Worker<A>,
+    ) -> bool {
+        !self.query(trace, name, worker).is_empty()
+    }
+
+    fn nodes<A: Allocate>(
+        &mut self,
+        nodes: &mut TraceHandle<N, Dependencies<N>>,
+        downstream: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<N> {
+        self.advance(worker);
+        self.compact(nodes);
+        self.compact(downstream);
+        let mut nodes = self.gather(nodes, worker);
+        let mut downstream = self.gather(downstream, worker);
+        let mut ret: Vec<N> = self
+            .read(&mut nodes)
+            .into_iter()
+            .map(|d| d.0)
+            .chain(self.read(&mut downstream).into_iter().map(|d| d.0))
+            .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+
+    fn edges<A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<N>,
+        worker: &mut Worker<A>,
+    ) -> Vec<(N, N)> {
+        self.advance(worker);
+        self.compact(trace);
+        let mut trace = self.gather(trace, worker);
+        self.read(&mut trace)
+            .into_iter()
+            .flat_map(|(k, vs)| vs.into_iter().map(move |v| (k.clone(), v)))
+            .collect()
+    }
+
+    fn read<K: ExchangeData, V: ExchangeData>(
+        &self,
+        trace: &mut TraceHandle<K, V>,
+    ) -> Vec<(K, Vec<V>)> {
+        self.read_at(trace, self.counter)
+    }
+
+    fn read_at<K: ExchangeData, V: ExchangeData>(
+        &self,
+        trace: &mut TraceHandle<K, V>,
+        time: Timestamp,
+    ) -> Vec<(K, Vec<V>)> {
+        let mut ret = vec![];
+        self.read_with(trace, time, |key, values| {
+            ret.push((key, values));
+            true
+        });
+        ret
+    }
+
+    /// Like `scan`, with owned keys and values. `trace` has to be held by
+    /// every worker, as query results are, see `held`, or `gather`ed.
+    fn read_with<K: ExchangeData, V: ExchangeData>(
+        &self,
+        trace: &mut TraceHandle<K, V>,
+        time: Timestamp,
+        mut f: impl FnMut(K, Vec<V>) -> bool,
+    ) {
+        self.scan(trace, time, |key, values| {
+            f(key.clone(), values.into_iter().cloned().collect())
+        })
+    }
+
+    /// A copy of `trace` kept by every worker, which otherwise only hold
+    /// their own shard of it, so that they all read the same and go on to
+    /// build the same dataflows. With a single worker this is `trace`. Only
+    /// for reading all of a trace, a query exchanges just its result.
+    fn gather<K: ExchangeData + Hash, V: ExchangeData, A: Allocate>(
+        &mut self,
+        trace: &mut TraceHandle<K, V>,
+        worker: &mut Worker<A>,
+    ) -> TraceHandle<K, V> {
+        if worker.peers() == 1 {
+            return trace.clone();
+        }
+        let gathered = worker.dataflow(|scope| {
+            let gathered = trace
+                .import(scope)
+                .as_collection(|k, v| (k.clone(), v.clone()))
+                .inner
+                .broadcast()
+                .as_collection()
+                .arrange_core::<_, Spine<K, V>>(Pipeline, "Gather");
+            gathered.stream.probe_with(&mut self.probe);
+            gathered.trace
+        });
+        worker.step_while(|| self.probe.less_than(self.input.time()));
+        gathered
+    }
+
+    /// The current values of `name` in `trace`. Only sees the shard of this
+    /// worker, see `gather`.
+    fn lookup<K: ExchangeData, V: ExchangeData>(
+        &self,
+        trace: &mut TraceHandle<K, V>,
+        name: &K,
+    ) -> Vec<V> {
+        use timely::PartialOrder;
+
+        let (mut cursor, storage) = trace.cursor();
+        cursor.seek_key(&storage, name);
+        let mut values = vec![];
+        if !cursor.key_valid(&storage) || cursor.key(&storage) != name {
+            return values;
+        }
+        while cursor.val_valid(&storage) {
+            let mut copies = 0;
+            cursor.map_times(&storage, |time, diff| {
+                if time.less_equal(&self.counter) {
+                    copies += diff;
+                }
+            });
+            if copies > 0 {
+                values.push(cursor.val(&storage).clone());
+            }
+            cursor.step_val(&storage);
+        }
+        values
+    }
+
+    /// Walks `trace` as of `time` and hands every key with its values to
+    /// `f`, stopping early once `f` returns false. The values are borrowed
+    /// from the trace, so wide dependency lists are only copied by callers
+    /// that keep them. Only sees the shard of this worker, see `gather`.
+    fn scan<K: ExchangeData, V: ExchangeData>(
+        &self,
+        trace: &mut TraceHandle<K, V>,
+        time: Timestamp,
+        mut f: impl FnMut(&K, Vec<&V>) -> bool,
+    ) {
+        use timely::PartialOrder;
+
+        let (mut cursor, storage) = trace.cursor();
+        while cursor.key_valid(&storage) {
+            let mut values = vec![];
+            while cursor.val_valid(&storage) {
+                let mut copies = 0;
+                cursor.map_times(&storage, |t, diff| {
+                    if t.less_equal(&time) {
+                        copies += diff;
+                    }
+                });
+                if copies < 0 {
+                    panic!(
+                        "Negative multiplicity: {} for {:?}",
+                        copies,
+                        cursor.key(&storage)
+                    );
+                }
+                for _ in 0..copies {
+                    values.push(cursor.val(&storage));
+                }
+                cursor.step_val(&storage);
+            }
+            if !values.is_empty() && !f(cursor.key(&storage), values) {
+                return;
+            }
+            cursor.step_key(&storage);
+        }
+    }
+}
+
+fn run<N: Key>(rx: Receiver<Envelope<N>>, config: Config, storage: Option<Box<dyn Storage<N>>>) {
+    let threads = config.threads();
+    // the first worker of the process receives the calls and passes them on
+    // to the others, which take their inbox and the first of all the storage
+    let (peers, inboxes): (Vec<_>, Vec<_>) = (1..threads).map(|_| unbounded()).unzip();
+    let peers = Mutex::new(Some(peers));
+    let inboxes = Mutex::new(inboxes.into_iter().map(Some).collect::<Vec<_>>());
+    let storage = Mutex::new(storage);
+    timely::execute(config.timely(), move |worker| {
+        let _span = tracing::info_span!("worker", index = worker.index()).entered();
+        let storage = match worker.index() {
+            0 => storage.lock().unwrap().take(),
+            _ => None,
+        };
+        let (mut ctx, mut traces) = build(worker, config.clone(), storage);
+        match worker.index() % threads {
+            0 => {
+                let peers = peers.lock().unwrap().take().unwrap();
+                lead(&mut ctx, &mut traces, &rx, &peers, worker);
+            }
+            local => {
+                let inbox = inboxes.lock().unwrap()[local - 1].take().unwrap();
+                follow(&mut ctx, &mut traces, inbox, worker);
+            }
+        }
+    })
+    .unwrap();
+}
+
+/// What the first worker of a process has the others do, in the order it
+/// does it itself, so that they all build the same dataflows.
+#[derive(Clone)]
+enum Command<N> {
+    Handle(Vec<Envelope<N>>),
+    Expire(Instant),
+    Advance,
+    Release(Vec<usize>),
+}
+
+fn lead<N: Key, A: Allocate>(
+    ctx: &mut Context<N>,
+    traces: &mut Traces<N>,
+    rx: &Receiver<Envelope<N>>,
+    peers: &[Sender<Command<N>>],
+    worker: &mut Worker<A>,
+) {
+    let broadcast = |command: Command<N>| {
+        for peer in peers {
+            peer.send(command.clone()).unwrap();
+        }
+    };
+    loop {
+        // wake up for the earliest TTL even when no message arrives
+        let message = match ctx.expiries.values().min() {
+            Some(deadline) => match rx.recv_deadline(*deadline) {
+                Ok(d) => Some(d),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match rx.recv() {
+                Ok(d) => Some(d),
+                Err(_) => break,
+            },
+        };
+        if !ctx.expiries.is_empty() {
+            let now = Instant::now();
+            if ctx.expiring(now) {
+                broadcast(Command::Expire(now));
+                ctx.expire_at(traces, now, worker);
+            }
+        }
+        let envelope = match message {
+            Some(d) => d,
+            None => continue,
+        };
+        // whatever queued up meanwhile is handled in one go
+        let mut batch = vec![envelope];
+        batch.extend(rx.try_iter());
+        broadcast(Command::Handle(batch.clone()));
+        handle_batch(ctx, traces, batch, worker);
+        // subscribers are only notified once time advances, which queries
+        // do anyway, so updates advance it too once the queue drains. Other
+        // processes can't tell when this queue drains, so there it's always.
+        if ctx.subscriptions.get() > 0 && (ctx.config.processes() > 1 || rx.is_empty()) {
+            broadcast(Command::Advance);
+            ctx.advance(worker);
+        }
+        // only the first worker of the process sends to subscribers, so only
+        // it finds out about those that hung up
+        let abandoned = ctx.abandoned.take();
+        if !abandoned.is_empty() {
+            broadcast(Command::Release(abandoned.clone()));
+            ctx.release(abandoned, worker);
+        }
+    }
+}
+
+fn follow<N: Key, A: Allocate>(
+    ctx: &mut Context<N>,
+    traces: &mut Traces<N>,
+    inbox: Receiver<Command<N>>,
+    worker: &mut Worker<A>,
+) {
+    for command in inbox {
+        match command {
+            Command::Handle(batch) => handle_batch(ctx, traces, batch, worker),
+            Command::Expire(now) => ctx.expire_at(traces, now, worker),
+            Command::Advance => ctx.advance(worker),
+            Command::Release(indices) => ctx.release(indices, worker),
+        }
+    }
+}
+
+/// Installs the dataflow of the backend on `worker`, loading the nodes kept
+/// by `storage` and the write-ahead log.
+fn build<N: Key, A: Allocate>(
+    worker: &mut Worker<A>,
+    config: Config,
+    storage: Option<Box<dyn Storage<N>>>,
+) -> (Context<N>, Traces<N>) {
+    let threads = config.threads();
+    let mut ctx = Context::new(config);
+    ctx.leader = worker.index() == 0;
+    ctx.replies = worker.index() % threads == 0;
+    let mut traces = worker.dataflow::<Timestamp, _, _>(|scope| {
+        let stream = scope.input_from(&mut ctx.input);
+        let arranged =
+            upsert::arrange_from_upsert::<_, Spine<N, Dependencies<N>>>(&stream, &"lineage");
+        let meta_stream = scope.input_from(&mut ctx.meta_input);
+        let metadata =
+            upsert::arrange_from_upsert::<_, Spine<N, MetaVal>>(&meta_stream, &"metadata");
+        arranged.stream.probe_with(&mut ctx.probe);
+        metadata.stream.probe_with(&mut ctx.probe);
+        let type_stream = scope.input_from(&mut ctx.type_input);
+        let types = upsert::arrange_from_upsert::<_, Spine<N, NodeType>>(&type_stream, &"types");
+        types.stream.probe_with(&mut ctx.probe);
+        let audit = scope
+            .input_from(&mut ctx.audit_input)
+            .as_collection()
+            .arrange_by_key();
+        audit.stream.probe_with(&mut ctx.probe);
+
+        let tombstone_stream = scope.input_from(&mut ctx.tombstone_input);
+        let tombstones =
+            upsert::arrange_from_upsert::<_, Spine<N, ()>>(&tombstone_stream, &"tombstones");
+        tombstones.stream.probe_with(&mut ctx.probe);
+        let hidden = tombstones.as_collection(|k, _| k.clone());
+
+        // soft-deleted nodes are hidden from every query, along with the
+        // edges pointing at them
+        let visible = arranged
+            .as_collection(|k, v| (k.clone(), v.clone()))
+            .antijoin(&hidden)
+            .arrange_by_key();
+        visible.stream.probe_with(&mut ctx.probe);
+        let edges = visible
+            .as_collection(|k, v| (k.clone(), v.clone()))
+            .flat_map(|(k, vs)| {
+                vs.iter()
+                    .map(|v| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .map(|(k, v)| (v.0.clone(), (k, v)))
+            .antijoin(&hidden)
+            .map(|(_, edge)| edge);
+        let upstream_typed = edges.map(|(k, (v, kind, _, _))| (k, (v, kind)));
+        let downstream_typed = upstream_typed.map(|(k, (v, kind))| (v, (k, kind)));
+        let upstream_weighted = edges.map(|(k, (v, _, weight, _))| (k, (v, weight)));
+        let upstream_valid = edges.map(|(k, (v, _, _, valid))| (k, (v, valid)));
+        let upstream = upstream_typed.map(|(k, (v, _))| (k, v)).distinct();
+        let downstream = upstream.map(|(k, v)| (v, k));
+
+        let upstream = upstream.arrange_by_key();
+        let downstream = downstream.arrange_by_key();
+        upstream.stream.probe_with(&mut ctx.probe);
+        downstream.stream.probe_with(&mut ctx.probe);
+
+        let (upstream_closure, downstream_closure) = if ctx.config.maintain_closure {
+            let reach = reachability(&upstream);
+            let upstream_closure = reach.arrange_by_key();
+            let downstream_closure = reach.map(|(k, v)| (v, k)).arrange_by_key();
+            upstream_closure.stream.probe_with(&mut ctx.probe);
+            downstream_closure.stream.probe_with(&mut ctx.probe);
+            (Some(upstream_closure.trace), Some(downstream_closure.trace))
+        } else {
+            (None, None)
+        };
+
+        // the standing queries, asked by adding to their inputs
+        let by_direction = |asks: &Collection<_, Ask<N>>, upstream: bool| {
+            asks.filter(move |ask| ask.0 == upstream).map(|ask| ask.1)
+        };
+        let asks = scope.input_from(&mut ctx.lookup_input).as_collection();
+        let lookups = held(
+            &upstream
+                .semijoin(&by_direction(&asks, true))
+                .map(|(name, d)| ((true, name), d))
+                .concat(
+                    &downstream
+                        .semijoin(&by_direction(&asks, false))
+                        .map(|(name, d)| ((false, name), d)),
+                ),
+        );
+        // a cascade is a k-hop query without a `k`, so both are answered by
+        // one reach per direction
+        let cascade_asks = scope.input_from(&mut ctx.cascade_input).as_collection();
+        let hop_asks: Collection<_, HopAsk<N>> =
+            scope.input_from(&mut ctx.hop_input).as_collection();
+        let reach_asks = |upstream: bool| {
+            by_direction(&cascade_asks, upstream)
+                .map(|name| (name, None))
+                .concat(
+                    &hop_asks
+                        .filter(move |ask| ask.0 == upstream)
+                        .map(|(_, name, k)| (name, Some(k))),
+                )
+        };
+        let reached = reach_edges(&upstream, &reach_asks(true))
+            .map(|((name, k), edge)| ((true, name, k), edge))
+            .concat(
+                &reach_edges(&downstream, &reach_asks(false))
+                    .map(|((name, k), edge)| ((false, name, k), edge)),
+            );
+        let cascades = held(
+            &reached
+                .filter(|((_, _, k), _)| k.is_none())
+                .map(|((upstream, name, _), edge)| ((upstream, name), edge)),
+        );
+        let hops = held(
+            &reached.flat_map(|((upstream, name, k), edge)| k.map(|k| ((upstream, name, k), edge))),
+        );
+        lookups.stream.probe_with(&mut ctx.probe);
+        cascades.stream.probe_with(&mut ctx.probe);
+        hops.stream.probe_with(&mut ctx.probe);
+
+        Traces {
+            upserted: arranged.trace,
+            nodes: visible.trace,
+            upstream: upstream.trace,
+            downstream: downstream.trace,
+            upstream_typed: upstream_typed.arrange_by_key().trace,
+            downstream_typed: downstream_typed.arrange_by_key().trace,
+            upstream_weighted: upstream_weighted.arrange_by_key().trace,
+            upstream_valid: upstream_valid.arrange_by_key().trace,
+            metadata: metadata.trace,
+            types: types.trace,
+            tombstones: tombstones.trace,
+            audit: audit.trace,
+            upstream_closure,
+            downstream_closure,
+            lookups: lookups.trace,
+            cascades: cascades.trace,
+            hops: hops.trace,
+        }
+    });
+
+    if let Some(mut storage) = storage {
+        let nodes = storage.load().expect("failed to load from storage");
+        for (name, dependencies) in nodes {
+            ctx.input
+                .send((name, Some(dependencies.into()), ctx.counter));
+        }
+        ctx.storage = Some(storage);
+    }
+    if let Some(path) = ctx.config.wal.clone().filter(|_| ctx.leader) {
+        ctx.replay(&path)
+            .expect("failed to replay the write-ahead log");
+    }
+    (ctx, traces)
+}
+
+/// Handles `batch` in order. Updates don't advance the time, so those
+/// between two queries land in the dataflow together, and every run of
+/// consecutive queries the standing queries answer takes one round of the
+/// worker rather than one each, see `answer_together`.
+fn handle_batch<N: Key, A: Allocate>(
+    ctx: &mut Context<N>,
+    traces: &mut Traces<N>,
+    batch: Vec<Envelope<N>>,
+    worker: &mut Worker<A>,
+) {
+    let mut run = vec![];
+    for envelope in batch {
+        let standing = match &envelope.message {
+            Message::Dependencies { .. }
+            | Message::Dependents { .. }
+            | Message::DependenciesK { .. }
+            | Message::DependentsK { .. } => true,
+            Message::DependenciesCascade { .. } => traces.upstream_closure.is_none(),
+            Message::DependentsCascade { .. } => traces.downstream_closure.is_none(),
+            _ => false,
+        };
+        if standing {
+            run.push(envelope);
+        } else {
+            answer_together(ctx, traces, std::mem::take(&mut run), worker);
+            handle(ctx, traces, envelope, worker);
+        }
+    }
+    answer_together(ctx, traces, run, worker);
+}
+
+/// Answers lookups, cascades and k-hop queries, posing all of their asks
+/// before a single `advance`.
+fn answer_together<N: Key, A: Allocate>(
+    ctx: &mut Context<N>,
+    traces: &mut Traces<N>,
+    run: Vec<Envelope<N>>,
+    worker: &mut Worker<A>,
+) {
+    if run.len() < 2 {
+        for envelope in run {
+            handle(ctx, traces, envelope, worker);
+        }
+        return;
+    }
+
+    let (mut lookups, mut cascades, mut hops) = (vec![], vec![], vec![]);
+    for envelope in &run {
+        match &envelope.message {
+            Message::Dependencies { name, .. } => lookups.push((true, ctx.resolve(name.clone()))),
+            Message::Dependents { name, .. } => lookups.push((false, ctx.resolve(name.clone()))),
+            Message::DependenciesCascade { name, .. } => {
+                cascades.push((true, ctx.resolve(name.clone())))
+            }
+            Message::DependentsCascade { name, .. } => {
+                cascades.push((false, ctx.resolve(name.clone())))
+            }
+            Message::DependenciesK { name, k, .. } if *k > 0 => {
+                hops.push((true, ctx.resolve(name.clone()), *k))
+            }
+            Message::DependentsK { name, k, .. } if *k > 0 => {
+                hops.push((false, ctx.resolve(name.clone()), *k))
+            }
+            _ => {}
+        }
+    }
+    ctx.pose(|ctx| &mut ctx.lookup_input, &lookups);
+    ctx.pose(|ctx| &mut ctx.cascade_input, &cascades);
+    ctx.pose(|ctx| &mut ctx.hop_input, &hops);
+    ctx.advance(worker);
+    ctx.compact(&mut traces.upstream);
+    ctx.compact(&mut traces.downstream);
+    let mut lookups = ctx
+        .collect(|ctx| &mut ctx.lookup_input, &mut traces.lookups, lookups)
+        .into_iter();
+    let mut cascades = ctx
+        .collect(|ctx| &mut ctx.cascade_input, &mut traces.cascades, cascades)
+        .into_iter()
+        .map(grouped);
+    let mut hops = ctx
+        .collect(|ctx| &mut ctx.hop_input, &mut traces.hops, hops)
+        .into_iter()
+        .map(grouped);
+
+    for Envelope {
+        message,
+        span,
+        wake,
+    } in run
+    {
+        ctx.mutation += 1;
+        let _span =
+            tracing::debug_span!(parent: &span, "handle", mutation = ctx.mutation).entered();
+        match message {
+            Message::Dependencies { tx, .. } | Message::Dependents { tx, .. } => {
+                ctx.reply(&tx, lookups.next().unwrap_or_default())
+            }
+            Message::DependenciesCascade { tx, .. } | Message::DependentsCascade { tx, .. } => {
+                ctx.reply(&tx, cascades.next().unwrap_or_default())
+            }
+            Message::DependenciesK { k, tx, .. } | Message::DependentsK { k, tx, .. } => {
+                let d = match k {
+                    0 => HashMap::new(),
+                    _ => hops.next().unwrap_or_default(),
+                };
+                ctx.reply(&tx, d)
+            }
+            _ => unreachable!("not answered by the standing queries"),
+        }
+        ctx.wake(wake);
+    }
+}
+
+fn handle<N: Key, A: Allocate>(
+    ctx: &mut Context<N>,
+    traces: &mut Traces<N>,
+    envelope: Envelope<N>,
+    worker: &mut Worker<A>,
+) {
+    let Envelope {
+        message,
+        span,
+        wake,
+    } = envelope;
+    ctx.mutation += 1;
+    let _span = tracing::debug_span!(parent: &span, "handle", mutation = ctx.mutation).entered();
+    let first = worker.next_dataflow_index();
+    match message {
+        Message::Exists { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.exists(&mut traces.nodes, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Nodes { tx } => {
+            let d = ctx.nodes(&mut traces.nodes, &mut traces.downstream, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Edges { tx } => {
+            let d = ctx.edges(&mut traces.upstream, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Roots { tx } => {
+            let d = ctx.query_boundary(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::Leaves { tx } => {
+            let d = ctx.query_boundary(
+                &mut traces.nodes,
+                &mut traces.downstream,
+                &mut traces.upstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::TopologicalOrder { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_topological_order(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                name,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::HasCycle { tx } => {
+            let d = ctx.has_cycle(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::Dependencies { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_edges(traces, true, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Dependents { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_edges(traces, false, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascade { name, tx } => {
+            let name = ctx.resolve(name);
+            let names = vec![name];
+            let d = match &mut traces.upstream_closure {
+                Some(closure) => {
+                    ctx.query_cascade_closed(closure, &mut traces.upstream, names, worker)
+                }
+                None => ctx.query_cascade_union(traces, true, names, worker),
+            };
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeMany { names, tx } => {
+            let names = ctx.resolve_all(names);
+            let d = match &mut traces.upstream_closure {
+                Some(closure) => {
+                    ctx.query_cascade_closed(closure, &mut traces.upstream, names, worker)
+                }
+                None => ctx.query_cascade_union(traces, true, names, worker),
+            };
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascade { name, tx } => {
+            let name = ctx.resolve(name);
+            let names = vec![name];
+            let d = match &mut traces.downstream_closure {
+                Some(closure) => {
+                    ctx.query_cascade_closed(closure, &mut traces.downstream, names, worker)
+                }
+                None => ctx.query_cascade_union(traces, false, names, worker),
+            };
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeMany { names, tx } => {
+            let names = ctx.resolve_all(names);
+            let d = match &mut traces.downstream_closure {
+                Some(closure) => {
+                    ctx.query_cascade_closed(closure, &mut traces.downstream, names, worker)
+                }
+                None => ctx.query_cascade_union(traces, false, names, worker),
+            };
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesClosure { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_closure(&mut traces.upstream, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsClosure { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_closure(&mut traces.downstream, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::AllPaths {
+            from,
+            to,
+            max_depth,
+            tx,
+        } => {
+            let from = ctx.resolve(from);
+            let to = ctx.resolve(to);
+            let d = ctx.query_paths(&mut traces.upstream, from, to, max_depth, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Distance { from, to, tx } => {
+            let from = ctx.resolve(from);
+            let to = ctx.resolve(to);
+            let d = ctx.query_distance(&mut traces.upstream, from, to, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::CommonDependencies { a, b, tx } => {
+            let a = ctx.resolve(a);
+            let b = ctx.resolve(b);
+            let d = ctx.query_common(&mut traces.upstream, a, b, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::CommonDependents { a, b, tx } => {
+            let a = ctx.resolve(a);
+            let b = ctx.resolve(b);
+            let d = ctx.query_common(&mut traces.downstream, a, b, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DiffDependencies { a, b, tx } => {
+            let a = ctx.resolve(a);
+            let b = ctx.resolve(b);
+            let d = ctx.query_diff(&mut traces.upstream, a, b, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Impact { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_impact(traces, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::SubgraphBetween { sources, sinks, tx } => {
+            let sources = ctx.resolve_all(sources);
+            let sinks = ctx.resolve_all(sinks);
+            let d = ctx.query_subgraph(
+                &mut traces.upstream,
+                &mut traces.downstream,
+                sources,
+                sinks,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCount { name, cascade, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_count(&mut traces.upstream, name, cascade, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCount { name, cascade, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_count(&mut traces.downstream, name, cascade, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::TopDependents { n, tx } => {
+            let d = ctx.query_top(&mut traces.downstream, n, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeFiltered { name, types, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_filtered(
+                &mut traces.upstream,
+                &mut traces.types,
+                name,
+                types,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeFiltered { name, types, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_filtered(
+                &mut traces.downstream,
+                &mut traces.types,
+                name,
+                types,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeWithDepth { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_with_depth(&mut traces.upstream, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeWithDepth { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_with_depth(&mut traces.downstream, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeStream {
+            name,
+            batch_size,
+            tx,
+        } => {
+            let name = ctx.resolve(name);
+            ctx.query_cascade_stream(&mut traces.upstream, name, batch_size, tx, worker);
+        }
+        Message::DependentsCascadeStream {
+            name,
+            batch_size,
+            tx,
+        } => {
+            let name = ctx.resolve(name);
+            ctx.query_cascade_stream(&mut traces.downstream, name, batch_size, tx, worker);
+        }
+        Message::DependenciesCascadeUntil { name, stop, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_until(
+                &mut traces.upstream,
+                &mut traces.metadata,
+                name,
+                stop,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeUntil { name, stop, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_until(
+                &mut traces.downstream,
+                &mut traces.metadata,
+                name,
+                stop,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeBounded {
+            name,
+            max_nodes,
+            tx,
+        } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_bounded(&mut traces.upstream, name, max_nodes, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeBounded {
+            name,
+            max_nodes,
+            tx,
+        } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_bounded(&mut traces.downstream, name, max_nodes, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::CheapestPath { from, to, tx } => {
+            let from = ctx.resolve(from);
+            let to = ctx.resolve(to);
+            let d = ctx.query_cheapest_path(&mut traces.upstream_weighted, from, to, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Component { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_component(&mut traces.upstream, &mut traces.downstream, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Components { tx } => {
+            let d = ctx.query_components(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::StronglyConnectedComponents { tx } => {
+            let d = ctx.query_strong_components(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::Condensation { tx } => {
+            let d = ctx.query_condensation(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::TransitiveReduction { tx } => {
+            let d = ctx.query_transitive_reduction(&mut traces.upstream, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Siblings { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_siblings(&mut traces.upstream, &mut traces.downstream, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Orphans { tx } => {
+            let d = ctx.query_orphans(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::IsUpstreamOf { a, b, tx } => {
+            let a = ctx.resolve(a);
+            let b = ctx.resolve(b);
+            let d = match &mut traces.upstream_closure {
+                Some(closure) => ctx.reaches_closed(closure, b, a, worker),
+                None => ctx.reaches(&mut traces.upstream, b, a, worker),
+            };
+            ctx.reply(&tx, d);
+        }
+        Message::LowestCommonDependencies { a, b, tx } => {
+            let a = ctx.resolve(a);
+            let b = ctx.resolve(b);
+            let d = ctx.query_lowest_common(&mut traces.upstream, a, b, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::MaxDepth { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx
+                .query_levels(
+                    &mut traces.nodes,
+                    &mut traces.upstream,
+                    &mut traces.downstream,
+                    name.clone(),
+                    worker,
+                )
+                .get(&name)
+                .copied();
+            ctx.reply(&tx, d);
+        }
+        Message::CriticalPath { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_critical_path(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                name,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::Stats { tx } => {
+            let d = ctx.query_stats(
+                &mut traces.nodes,
+                &mut traces.upstream,
+                &mut traces.downstream,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::CriticalNodes { sink, tx } => {
+            let sink = ctx.resolve(sink);
+            let d = ctx.query_critical_nodes(&mut traces.upstream, sink, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::SharedUpstream { names, tx } => {
+            let names = ctx.resolve_all(names);
+            let d = ctx.query_shared(&mut traces.upstream, names, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeExcluding { name, exclude, tx } => {
+            let name = ctx.resolve(name);
+            let exclude = ctx.resolve_all(exclude);
+            let d = ctx.query_cascade_excluding(&mut traces.upstream, name, exclude, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeExcluding { name, exclude, tx } => {
+            let name = ctx.resolve(name);
+            let exclude = ctx.resolve_all(exclude);
+            let d = ctx.query_cascade_excluding(&mut traces.downstream, name, exclude, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Now { tx } => ctx.reply(&tx, ctx.counter),
+        Message::DependenciesAt { name, time, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_at(&mut traces.upstream, name, time, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeAt { name, time, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_at(&mut traces.downstream, vec![name], time, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Diff { name, from, to, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_diff_at(&mut traces.upstream, name, from, to, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::SubscribeDependentsCascade { name, tx } => {
+            let name = ctx.resolve(name);
+            ctx.subscribe_cascade(&mut traces.downstream, name, tx, worker);
+        }
+        Message::Changes { tx } => {
+            ctx.subscribe_edges(&mut traces.upstream, tx, worker);
+        }
+        Message::Audit { name, tx } => {
+            let name = ctx.resolve(name);
+            let mut d: Vec<_> = ctx
+                .query(&mut traces.audit, name, worker)
+                .into_iter()
+                .map(|(time, actor, reason, dependencies)| AuditEntry {
+                    time,
+                    actor,
+                    reason,
+                    dependencies,
+                })
+                .collect();
+            d.sort_by_key(|entry| entry.time);
+            ctx.reply(&tx, d);
+        }
+        Message::RegisterQuery { spec, tx } => {
+            let (trace, name) = match spec {
+                QuerySpec::DependenciesCascade(name) => (&mut traces.upstream, name),
+                QuerySpec::DependentsCascade(name) => (&mut traces.downstream, name),
+            };
+            let name = ctx.resolve(name);
+            let d = ctx.register_query(trace, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::PollQuery { handle, tx } => {
+            let d = ctx.poll_query(handle, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::SubscribeQuery { handle, tx } => ctx.subscribe_query(handle, tx),
+        Message::ReleaseQuery { handle } => ctx.release_query(handle, worker),
+        Message::TagSnapshot { label, tx } => {
+            ctx.tags.insert(label, ctx.counter);
+            ctx.reply(&tx, ctx.counter);
+        }
+        Message::Snapshot { label, tx } => ctx.reply(&tx, ctx.tags.get(&label).copied()),
+        Message::EdgeHistory { from, to, tx } => {
+            let d = ctx.edge_history(&mut traces.upstream, from, to, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::LastModified { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.last_modified(&mut traces.upserted, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::ChangedSince { time, tx } => {
+            let d = ctx.changed_since(&mut traces.upserted, time, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeBitemporal {
+            name,
+            valid,
+            time,
+            tx,
+        } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_valid(&mut traces.upstream_valid, name, valid, time, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Compact { tx } => {
+            ctx.compact_all(traces, worker);
+            ctx.reply(&tx, ());
+        }
+        Message::CompactionStats { tx } => {
+            let d = ctx.compaction_stats(traces);
+            ctx.reply(&tx, d);
+        }
+        Message::SaveSnapshot { path, tx } => {
+            let d = ctx.save_snapshot(&mut traces.upserted, &path, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::ExportGraphml { id, tx } => {
+            let d = ctx.export_graphml(traces, &*id, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::ExportGexf { id, tx } => {
+            let d = ctx.export_gexf(traces, &*id, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::BackupSince { time, path, tx } => {
+            let d = ctx.backup_since(&mut traces.upserted, time, &path, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::ApplyBackup { changes } => {
+            for (name, dependencies) in changes {
+                ctx.write(name, dependencies);
+            }
+            ctx.advance(worker);
+        }
+        Message::DependenciesCascadeWithMetadata { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_with_metadata(
+                &mut traces.upstream,
+                &mut traces.metadata,
+                name,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeWithMetadata { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_with_metadata(
+                &mut traces.downstream,
+                &mut traces.metadata,
+                name,
+                worker,
+            );
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesOfKind { name, kinds, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_of_kind(&mut traces.upstream_typed, name, kinds, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsOfKind { name, kinds, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_of_kind(&mut traces.downstream_typed, name, kinds, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesCascadeOfKind { name, kinds, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_of_kind(&mut traces.upstream_typed, name, kinds, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsCascadeOfKind { name, kinds, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_cascade_of_kind(&mut traces.downstream_typed, name, kinds, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Neighborhood { name, k, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_neighborhood(traces, name, k, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesAtDepth { name, depth, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_at_depth(&mut traces.upstream, name, depth, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsAtDepth { name, depth, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_at_depth(&mut traces.downstream, name, depth, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependenciesK { name, k, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_k(traces, true, name, k, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::DependentsK { name, k, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query_k(traces, false, name, k, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Upsert { name, dependencies } => {
+            let _ = ctx.upsert(traces, name, dependencies, worker);
+        }
+        Message::UpsertWithTtl {
+            name,
+            dependencies,
+            ttl,
+        } => {
+            if ctx
+                .upsert(traces, name.clone(), dependencies, worker)
+                .is_ok()
+            {
+                ctx.expiries.insert(name, Instant::now() + ttl);
+            }
+        }
+        Message::UpsertAt {
+            name,
+            dependencies,
+            time,
+        } => {
+            ctx.skip_to(time);
+            let _ = ctx.upsert(traces, name, dependencies, worker);
+        }
+        Message::TryUpsert {
+            name,
+            dependencies,
+            tx,
+        } => {
+            let d = ctx.upsert(traces, name, dependencies, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::UpsertBatch { batch } => {
+            ctx.upsert_batch(traces, batch, worker);
+            ctx.advance(worker);
+        }
+        Message::AddDependency { name, dependency } => {
+            let mut dependencies = ctx
+                .query(&mut traces.upserted, name.clone(), worker)
+                .pop()
+                .unwrap_or_default()
+                .to_vec();
+            if !dependencies.iter().any(|(d, _, _, _)| *d == dependency) {
+                dependencies.push((dependency, EdgeKind::new(), 1, ALWAYS));
+                let _ = ctx.upsert(traces, name, dependencies, worker);
+            }
+        }
+        Message::RemoveDependency { name, dependency } => {
+            let mut dependencies = ctx
+                .query(&mut traces.upserted, name.clone(), worker)
+                .pop()
+                .unwrap_or_default()
+                .to_vec();
+            let len = dependencies.len();
+            dependencies.retain(|(d, _, _, _)| *d != dependency);
+            if dependencies.len() != len {
+                ctx.write(name, Some(dependencies.into()));
+            }
+        }
+        Message::Delete { name } => {
+            let _ = ctx.delete(traces, name, worker);
+        }
+        Message::UpsertBy {
+            name,
+            dependencies,
+            by,
+        } => {
+            let names = dependencies.iter().map(|d| d.0.clone()).collect();
+            let d = ctx.upsert(traces, name.clone(), dependencies, worker);
+            if d.is_ok() {
+                ctx.record(name, Some(names), by);
+            }
+        }
+        Message::DeleteBy { name, by } => {
+            if ctx.delete(traces, name.clone(), worker).is_ok() {
+                ctx.record(name, None, by);
+            }
+        }
+        Message::DeleteAt { name, time } => {
+            ctx.skip_to(time);
+            let _ = ctx.delete(traces, name, worker);
+        }
+        Message::TryDelete { name, tx } => {
+            let d = ctx.delete(traces, name, worker);
+            ctx.reply(&tx, d);
+        }
+        Message::Rollback { n } => ctx.rollback(traces, n, worker),
+        Message::SoftDelete { name } => {
+            if ctx.leader {
+                ctx.tombstone_input.send((name, Some(()), ctx.counter))
+            }
+        }
+        Message::Restore { name } => {
+            if ctx.leader {
+                ctx.tombstone_input.send((name, None, ctx.counter))
+            }
+        }
+        Message::Alias { alias, target } => ctx.alias(alias, target),
+        Message::Rename { old, new } => ctx.rename(traces, old, new, false, worker),
+        Message::Merge { into, from } => ctx.rename(traces, from, into, true, worker),
+        Message::GetNodeType { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query(&mut traces.types, name, worker).pop();
+            ctx.reply(&tx, d);
+        }
+        Message::SetNodeType { name, node_type } => {
+            if ctx.leader {
+                ctx.type_input.send((name, node_type, ctx.counter))
+            }
+        }
+        Message::GetMetadata { name, tx } => {
+            let name = ctx.resolve(name);
+            let d = ctx.query(&mut traces.metadata, name, worker);
+            let d = d.into_iter().next().map(|m| m.into_iter().collect());
+            ctx.reply(&tx, d);
+        }
+        Message::SetMetadata { name, metadata } => {
+            let mut metadata: MetaVal = metadata.into_iter().collect();
+            metadata.sort();
+            let metadata = if metadata.is_empty() {
+                None
+            } else {
+                Some(metadata)
+            };
+            if ctx.leader {
+                ctx.meta_input.send((name, metadata, ctx.counter))
+            }
+        }
+    }
+    ctx.wake(wake);
+    ctx.drop_dataflows(first, worker);
+}
+
+fn cascade<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, N>,
+) -> Collection<G, (N, N)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    arranged.semijoin(query).iterate(|lineage| {
+        let targets = lineage.map(|kv| kv.1);
+        arranged
+            .enter(&lineage.scope())
+            .semijoin(&targets)
+            .concat(lineage)
+            .reduce(|_key, input, output| {
+                for (v, _) in input {
+                    output.push(((*v).clone(), 1));
+                }
+            })
+    })
+}
+
+/// Every `(query, (node, dependency))` edge of the nodes fewer than `k` hops
+/// away from the queried `(name, k)`, or of the whole cascade of `name`
+/// without a `k`, so that one dataflow answers for many queries at once.
+fn reach_edges<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, (N, Option<usize>)>,
+) -> Collection<G, ((N, Option<usize>), (N, N))>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    // `((query, node), hops)` with the fewest hops from the queried name
+    let roots = query
+        .filter(|(_, k)| *k != Some(0))
+        .map(|query| ((query.clone(), query.0), 0));
+    let reach = roots.iterate(|reach| {
+        let roots = roots.enter(&reach.scope());
+        reach
+            .filter(|((query, _), hops)| query.1.map_or(true, |k| hops + 1 < k))
+            .map(|((query, node), hops)| (node, (query, hops)))
+            .join_core(
+                &arranged.enter(&reach.scope()),
+                |_node, (query, hops), next| Some(((query.clone(), next.clone()), hops + 1)),
+            )
+            .concat(&roots)
+            .reduce(|_key, input, output| output.push((*input[0].0, 1)))
+    });
+    reach
+        .map(|((query, node), _)| (node, query))
+        .join_core(arranged, |node, query, dependency| {
+            Some((query.clone(), (node.clone(), dependency.clone())))
+        })
+}
+
+/// Arranges `answers` on every worker rather than on the one owning each
+/// key, see `Context::gather`.
+fn held<G, K, V>(answers: &Collection<G, (K, V)>) -> Arranged<G, TraceHandle<K, V>>
+where
+    G: Scope<Timestamp = Timestamp>,
+    K: ExchangeData + Hash,
+    V: ExchangeData,
+{
+    answers
+        .inner
+        .broadcast()
+        .as_collection()
+        .arrange_core::<_, Spine<K, V>>(Pipeline, "Held")
+}
+
+/// Like `cascade`, but nodes in `stop` are reached without being expanded.
+fn cascade_until<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, N>,
+    stop: &Collection<G, N>,
+) -> Collection<G, (N, N)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    arranged.semijoin(query).iterate(|lineage| {
+        let stop = stop.enter(&lineage.scope());
+        let targets = lineage
+            .map(|kv| (kv.1, ()))
+            .antijoin(&stop)
+            .map(|(x, ())| x);
+        arranged
+            .enter(&lineage.scope())
+            .semijoin(&targets)
+            .concat(lineage)
+            .reduce(|_key, input, output| {
+                for (v, _) in input {
+                    output.push(((*v).clone(), 1));
+                }
+            })
+    })
+}
+
+fn closure<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, N>,
+) -> Collection<G, N>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    arranged
+        .semijoin(query)
+        .map(|kv| kv.1)
+        .distinct()
+        .iterate(|reach| {
+            arranged
+                .enter(&reach.scope())
+                .semijoin(reach)
+                .map(|kv| kv.1)
+                .concat(reach)
+                .distinct()
+        })
+}
+
+/// Minimum hop count from the queried names to every node they reach.
+fn distances<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, N>,
+) -> Collection<G, (N, usize)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    distances_within(arranged, query, usize::MAX)
+}
+
+/// Like `distances`, but stops exploring at `limit` hops.
+fn distances_within<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, N>,
+    limit: usize,
+) -> Collection<G, (N, usize)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    let roots = query.map(|x| (x, 0));
+    roots.iterate(|dists| {
+        let arranged = arranged.enter(&dists.scope());
+        let roots = roots.enter(&dists.scope());
+        dists
+            .filter(move |(_, dist)| *dist < limit)
+            .join_core(&arranged, |_node, dist, next| {
+                Some((next.clone(), dist + 1))
+            })
+            .concat(&roots)
+            .reduce(|_node, input, output| output.push((*input[0].0, 1)))
+    })
+}
+
+/// Like `distances`, but explores one more level only while no more than
+/// `max_nodes` nodes besides the queried ones have been reached, so that
+/// the last level explored is the one going over it.
+fn distances_until<G, N>(
+    arranged: &Arranged<G, TraceHandle<N>>,
+    query: &Collection<G, N>,
+    max_nodes: usize,
+) -> Collection<G, (N, usize)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    let roots = query.map(|x| (x, 0));
+    roots.iterate(|dists| {
+        let arranged = arranged.enter(&dists.scope());
+        let roots = roots.enter(&dists.scope());
+        let over = dists
+            .filter(|(_, dist)| *dist > 0)
+            .map(|_| ())
+            .count()
+            .filter(move |(_, count)| *count as usize > max_nodes)
+            .map(|_| ());
+        dists
+            .map(|dist| ((), dist))
+            .antijoin(&over)
+            .map(|((), dist)| dist)
+            .join_core(&arranged, |_node, dist, next| {
+                Some((next.clone(), dist + 1))
+            })
+            .concat(dists)
+            .concat(&roots)
+            .reduce(|_node, input, output| output.push((*input[0].0, 1)))
+    })
+}
+
+/// Labels every node with the smallest node it is connected to through
+/// `edges`, which should contain both directions of every edge.
+fn components<G, N>(
+    edges: &Arranged<G, TraceHandle<N>>,
+    nodes: &Collection<G, N>,
+) -> Collection<G, (N, N)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    let labels = nodes.map(|x| (x.clone(), x));
+    labels.iterate(|inner| {
+        let edges = edges.enter(&inner.scope());
+        let labels = labels.enter(&inner.scope());
+        inner
+            .join_core(&edges, |_node, label, next| {
+                Some((next.clone(), label.clone()))
+            })
+            .concat(&labels)
+            .reduce(|_node, input, output| output.push(((*input[0].0).clone(), 1)))
+    })
+}
+
+/// Every `(from, to)` pair such that `to` is reachable from `from` over at
+/// least one edge.
+fn reachability<G, N>(edges: &Arranged<G, TraceHandle<N>>) -> Collection<G, (N, N)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    let direct = edges.as_collection(|k, v| (k.clone(), v.clone()));
+    direct.iterate(|reach| {
+        let edges = edges.enter(&reach.scope());
+        reach
+            .map(|(from, via)| (via, from))
+            .join_core(&edges, |_via, from, to| Some((from.clone(), to.clone())))
+            .concat(&direct.enter(&reach.scope()))
+            .distinct()
+    })
+}
+
+/// Labels every node with the smallest node of its strongly connected
+/// component.
+fn strong_components<G, N>(
+    edges: &Arranged<G, TraceHandle<N>>,
+    nodes: &Collection<G, N>,
+) -> Collection<G, (N, N)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    let reach = reachability(edges);
+    reach
+        .map(|pair| (pair, ()))
+        .semijoin(&reach.map(|(from, to)| (to, from)))
+        .map(|(pair, ())| pair)
+        .concat(&nodes.map(|x| (x.clone(), x)))
+        .reduce(|_node, input, output| output.push(((*input[0].0).clone(), 1)))
+}
+
+/// Adds the batches of `trace`, and the updates they hold, to `stats`.
+fn tally<K: ExchangeData, V: ExchangeData>(
+    trace: &mut TraceHandle<K, V>,
+    stats: &mut CompactionStats,
+) {
+    stats.traces += 1;
+    trace.map_batches(|batch| {
+        stats.batches += 1;
+        stats.updates += batch.len();
+    });
+}
+
+/// `(node, dependency)` edges as the dependencies of each node, in the order
+/// read.
+fn grouped<N: Key>(edges: Vec<(N, N)>) -> HashMap<N, Vec<N>> {
+    let mut grouped: HashMap<N, Vec<N>> = HashMap::new();
+    for (node, dependency) in edges {
+        grouped.entry(node).or_default().push(dependency);
+    }
+    grouped
+}
+
+/// Length of the longest dependency chain below every node. Nodes on a
+/// cycle, or depending on one, never get a level.
+fn levels<G, N>(
+    upstream: &Arranged<G, TraceHandle<N>>,
+    downstream: &Arranged<G, TraceHandle<N>>,
+    nodes: &Collection<G, N>,
+) -> Collection<G, (N, usize)>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    let degrees = upstream.as_collection(|k, _| k.clone()).count();
+    let roots = nodes
+        .map(|x| (x, ()))
+        .antijoin(&degrees.map(|(k, _)| k))
+        .map(|(x, ())| (x, 0));
+    roots.iterate(|levels| {
+        let downstream = downstream.enter(&levels.scope());
+        let degrees = degrees.enter(&levels.scope());
+        let roots = roots.enter(&levels.scope());
+        levels
+            .join_core(&downstream, |_dep, level, name| {
+                Some((name.clone(), *level))
+            })
+            .reduce(|_name, input, output| {
+                let ready: isize = input.iter().map(|(_, diff)| *diff).sum();
+                output.push(((ready, *input[input.len() - 1].0), 1));
+            })
+            .join(&degrees)
+            .filter(|(_, ((ready, _), degree))| ready == degree)
+            .map(|(name, ((_, level), _))| (name, level + 1))
+            .concat(&roots)
+    })
+}
+
+fn of_kind<G, N>(
+    arranged: &Arranged<G, TraceHandle<N, (N, EdgeKind)>>,
+    kinds: Vec<EdgeKind>,
+) -> Arranged<G, TraceHandle<N>>
+where
+    G: Scope<Timestamp = Timestamp>,
+    N: Key,
+{
+    arranged
+        .flat_map_ref(move |k, (v, kind)| {
+            if kinds.contains(kind) {
+                Some((k.clone(), v.clone()))
+            } else {
+                None
+            }
+        })
+        .arrange_by_key()
+}
@@ -0,0 +1,167 @@
+//! The file format of the write-ahead log, snapshots and backups: a header
+//! naming the format and its version, then records framed by their length
+//! and a CRC-32 of their JSON encoding. A torn, corrupt or foreign file is
+//! told apart from a valid one instead of being misread.
+
+use std::io;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+const MAGIC: &[u8; 7] = b"LINEAGE";
+const VERSION: u8 = 1;
+/// Length and checksum, both little-endian `u32`s.
+const FRAME: usize = 8;
+
+/// What every file starts with.
+pub(crate) fn header() -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes
+}
+
+/// Appends `record` to `bytes`, to follow the `header` or the records
+/// before it.
+pub(crate) fn append<T: Serialize>(record: &T, bytes: &mut Vec<u8>) {
+    let encoded = serde_json::to_vec(record).expect("records encode to JSON");
+    bytes.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32(&encoded).to_le_bytes());
+    bytes.extend_from_slice(&encoded);
+}
+
+/// The records of `bytes` up to the first one that is incomplete or fails
+/// its checksum, e.g. torn by a crash, with the number of bytes left from
+/// there on. A missing header, or a record passing its checksum that does
+/// not decode, is `InvalidData`.
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<(Vec<T>, usize)> {
+    let version = match bytes.strip_prefix(&MAGIC[..]) {
+        Some([version, ..]) => *version,
+        _ => return Err(invalid("not a lineage file")),
+    };
+    if version != VERSION {
+        let msg = format!("unsupported lineage file version {version}");
+        return Err(invalid(&msg));
+    }
+    let mut records = vec![];
+    let mut rest = &bytes[MAGIC.len() + 1..];
+    while rest.len() >= FRAME {
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(rest[4..FRAME].try_into().unwrap());
+        let record = match rest[FRAME..].get(..len) {
+            Some(record) if crc32(record) == checksum => record,
+            _ => break,
+        };
+        let record = serde_json::from_slice(record)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        records.push(record);
+        rest = &rest[FRAME + len..];
+    }
+    Ok((records, rest.len()))
+}
+
+/// Like `decode`, refusing a file with anything left over.
+pub(crate) fn decode_all<T: DeserializeOwned>(bytes: &[u8]) -> io::Result<Vec<T>> {
+    match decode(bytes)? {
+        (records, 0) => Ok(records),
+        _ => Err(invalid("truncated or corrupt lineage file")),
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// CRC-32 (IEEE) of `bytes`.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(records: &[(&str, u64)]) -> Vec<u8> {
+        let mut bytes = header();
+        for record in records {
+            append(record, &mut bytes);
+        }
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = file(&[("a", 1), ("b", 2)]);
+        let records: Vec<(String, u64)> = decode_all(&bytes).unwrap();
+        assert_eq!(records, vec![("a".to_string(), 1), ("b".to_string(), 2)]);
+        let (records, torn) = decode::<(String, u64)>(&header()).unwrap();
+        assert!(records.is_empty());
+        assert_eq!(torn, 0);
+    }
+
+    #[test]
+    fn torn_tail() {
+        let whole = file(&[("a", 1), ("b", 2)]);
+        let first = file(&[("a", 1)]).len();
+        // cut in the frame and in the record of the last one
+        for end in [first + 3, whole.len() - 1] {
+            let (records, torn) = decode::<(String, u64)>(&whole[..end]).unwrap();
+            assert_eq!(records, vec![("a".to_string(), 1)]);
+            assert_eq!(torn, end - first);
+            assert!(decode_all::<(String, u64)>(&whole[..end]).is_err());
+        }
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut bytes = file(&[("a", 1), ("b", 2), ("c", 3)]);
+        let first = file(&[("a", 1)]).len();
+        let second = file(&[("a", 1), ("b", 2)]).len();
+        // a flipped bit in the second record stops decoding there
+        bytes[second - 2] ^= 1;
+        let (records, torn) = decode::<(String, u64)>(&bytes).unwrap();
+        assert_eq!(records, vec![("a".to_string(), 1)]);
+        assert_eq!(torn, bytes.len() - first);
+    }
+
+    #[test]
+    fn foreign_header() {
+        for bytes in [&b""[..], b"LINEAG", b"{\"a\": 1}\n", b"lineage\x01"] {
+            let err = decode::<(String, u64)>(bytes).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut bytes = file(&[("a", 1)]);
+        bytes[MAGIC.len()] = VERSION + 1;
+        let err = decode::<(String, u64)>(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn checksum_passing_garbage() {
+        let mut bytes = header();
+        bytes.extend_from_slice(&3u32.to_le_bytes());
+        bytes.extend_from_slice(&crc32(b"{{{").to_le_bytes());
+        bytes.extend_from_slice(b"{{{");
+        let err = decode::<(String, u64)>(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,271 @@
+//! `Shared`, the dependency lists as the traces hold them.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::ops::Deref;
+use std::sync::Arc;
+
+use abomonation::Abomonation;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An immutable `Arc<[T]>`, so that the dataflow copies a pointer rather
+/// than the whole list every time it clones a value.
+///
+/// Timely exchanges values between processes with abomonation, which reads
+/// them back in place from the received bytes. A list read that way borrows
+/// those bytes, like an abomonated `Vec` does, and is copied into an `Arc`
+/// of its own once cloned.
+pub struct Shared<T> {
+    // `None` for a list borrowing received bytes
+    owner: Option<Arc<[T]>>,
+    ptr: *const T,
+    len: usize,
+}
+
+// like `Arc<[T]>`, the borrowed bytes are never written to
+unsafe impl<T: Send + Sync> Send for Shared<T> {}
+unsafe impl<T: Send + Sync> Sync for Shared<T> {}
+
+impl<T> From<Vec<T>> for Shared<T> {
+    fn from(items: Vec<T>) -> Self {
+        let owner: Arc<[T]> = items.into();
+        Shared {
+            ptr: owner.as_ptr(),
+            len: owner.len(),
+            owner: Some(owner),
+        }
+    }
+}
+
+impl<T> Default for Shared<T> {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
+
+impl<T> Deref for Shared<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // points into `owner`, or into the bytes the list was read from
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T: Clone> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        match &self.owner {
+            Some(owner) => Shared {
+                owner: Some(Arc::clone(owner)),
+                ptr: self.ptr,
+                len: self.len,
+            },
+            None => self.to_vec().into(),
+        }
+    }
+}
+
+impl<T: PartialEq> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: Eq> Eq for Shared<T> {}
+
+impl<T: PartialOrd> PartialOrd for Shared<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+}
+
+impl<T: Ord> Ord for Shared<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: Hash> Hash for Shared<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: Serialize> Serialize for Shared<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Shared<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Shared::from)
+    }
+}
+
+/// Laid out like the abomonation of a `Vec`: the items, then what each of
+/// them owns.
+impl<T: Abomonation> Abomonation for Shared<T> {
+    unsafe fn entomb<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let items: &[T] = self;
+        let bytes =
+            std::slice::from_raw_parts(items.as_ptr() as *const u8, std::mem::size_of_val(items));
+        write.write_all(bytes)?;
+        for item in items {
+            item.entomb(write)?;
+        }
+        Ok(())
+    }
+
+    unsafe fn exhume<'a, 'b>(&'a mut self, bytes: &'b mut [u8]) -> Option<&'b mut [u8]> {
+        let binary_len = self.len * std::mem::size_of::<T>();
+        if binary_len > bytes.len() {
+            return None;
+        }
+        let (mine, mut rest) = bytes.split_at_mut(binary_len);
+        // `owner` was copied as it was on the sending side, it must not be
+        // dropped here
+        std::ptr::write(&mut self.owner, None);
+        self.ptr = mine.as_ptr() as *const T;
+        let items = std::slice::from_raw_parts_mut(mine.as_mut_ptr() as *mut T, self.len);
+        for item in items {
+            let temp = rest;
+            rest = item.exhume(temp)?;
+        }
+        Some(rest)
+    }
+
+    fn extent(&self) -> usize {
+        let items: &[T] = self;
+        std::mem::size_of_val(items) + items.iter().map(|item| item.extent()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use timely::dataflow::operators::{Exchange, Inspect, ToStream};
+
+    use super::*;
+
+    type Dependency = (String, String, u64, (u64, u64));
+
+    fn dependencies(n: usize) -> Vec<Dependency> {
+        (0..n)
+            .map(|i| {
+                (
+                    format!("node-{i}"),
+                    format!("kind-{i}"),
+                    i as u64,
+                    (i as u64, u64::MAX),
+                )
+            })
+            .collect()
+    }
+
+    fn entombed<T: Abomonation>(value: &T) -> Vec<u8> {
+        let mut bytes = vec![];
+        unsafe { abomonation::encode(value, &mut bytes).unwrap() };
+        assert_eq!(bytes.len(), abomonation::measure(value));
+        bytes
+    }
+
+    #[test]
+    fn round_trip() {
+        for n in [0, 1, 5] {
+            let shared: Shared<u64> = (0..n).collect::<Vec<_>>().into();
+            let mut bytes = entombed(&shared);
+            let (exhumed, rest) =
+                unsafe { abomonation::decode::<Shared<u64>>(&mut bytes) }.unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(exhumed, &shared);
+        }
+    }
+
+    #[test]
+    fn round_trip_nested() {
+        let shared: Shared<Dependency> = dependencies(4).into();
+        let mut bytes = entombed(&shared);
+        let (exhumed, rest) =
+            unsafe { abomonation::decode::<Shared<Dependency>>(&mut bytes) }.unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(&**exhumed, &dependencies(4)[..]);
+    }
+
+    #[test]
+    fn truncated() {
+        let shared: Shared<Dependency> = dependencies(4).into();
+        let len = entombed(&shared).len();
+        for end in [0, len / 2, len - 1] {
+            let mut bytes = entombed(&shared);
+            let decoded = unsafe { abomonation::decode::<Shared<Dependency>>(&mut bytes[..end]) };
+            assert!(decoded.is_none());
+        }
+    }
+
+    #[test]
+    fn clone_after_exhume() {
+        let shared: Shared<Dependency> = dependencies(3).into();
+        let mut bytes = entombed(&shared);
+        let cloned = {
+            let (exhumed, _) =
+                unsafe { abomonation::decode::<Shared<Dependency>>(&mut bytes) }.unwrap();
+            exhumed.clone()
+        };
+        // the clone owns its items, the bytes it was read from can go
+        bytes.iter_mut().for_each(|b| *b = 0);
+        drop(bytes);
+        assert_eq!(cloned, shared);
+        let again = cloned.clone();
+        drop(cloned);
+        assert_eq!(&*again, &dependencies(3)[..]);
+    }
+
+    #[test]
+    fn exchange_between_workers() {
+        let received = Arc::new(Mutex::new(vec![]));
+        let sink = Arc::clone(&received);
+        // serializes what goes between the workers, as between processes
+        let config = timely::Config {
+            communication: timely::CommunicationConfig::ProcessBinary(2),
+            worker: timely::WorkerConfig::default(),
+        };
+        timely::execute(config, move |worker| {
+            let index = worker.index();
+            let sink = Arc::clone(&sink);
+            worker.dataflow::<u64, _, _>(|scope| {
+                (0..4)
+                    .map(move |n| (index, Shared::from(dependencies(n))))
+                    .to_stream(scope)
+                    .exchange(|(_, shared)| shared.len() as u64)
+                    .inspect(move |(from, shared)| {
+                        // kept past the received bytes
+                        sink.lock().unwrap().push((*from, shared.clone()));
+                    });
+            });
+        })
+        .unwrap();
+
+        let mut received: Vec<_> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(from, shared)| (*from, shared.to_vec()))
+            .collect();
+        received.sort();
+        let mut expected: Vec<_> = (0..2)
+            .flat_map(|from| (0..4).map(move |n| (from, dependencies(n))))
+            .collect();
+        expected.sort();
+        assert_eq!(received, expected);
+    }
+}
@@ -0,0 +1,69 @@
+//! Where the differential backend mirrors its nodes, so that a restart
+//! loads them back instead of starting empty, see `with_storage`.
+
+use std::fmt;
+use std::io;
+
+use crate::lineage::{EdgeKind, ValidTime, Weight};
+
+/// A dependency as stored: the name depended on, the edge kind, weight and
+/// valid-time interval.
+pub type StoredDependency<N> = (N, EdgeKind, Weight, (ValidTime, ValidTime));
+
+/// A key-value store of node names and their dependency lists. Every
+/// change is written through before it reaches the dataflow, the traces
+/// themselves stay in memory.
+pub trait Storage<N>: Send + fmt::Debug {
+    /// Every stored node, read once on start.
+    fn load(&mut self) -> io::Result<Vec<(N, Vec<StoredDependency<N>>)>>;
+    fn put(&mut self, name: &N, dependencies: &[StoredDependency<N>]) -> io::Result<()>;
+    fn remove(&mut self, name: &N) -> io::Result<()>;
+}
+
+/// `Storage` in a sled database, names and dependency lists encoded as
+/// JSON.
+#[cfg(feature = "sled")]
+#[derive(Debug)]
+pub struct SledStorage<N> {
+    db: sled::Db,
+    _names: std::marker::PhantomData<fn() -> N>,
+}
+
+#[cfg(feature = "sled")]
+impl<N> SledStorage<N> {
+    pub fn open(path: impl AsRef<std::path::Path>) -> io::Result<Self> {
+        Ok(SledStorage {
+            db: sled::open(path)?,
+            _names: std::marker::PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl<N> Storage<N> for SledStorage<N>
+where
+    N: serde::Serialize + serde::de::DeserializeOwned,
+{
+    fn load(&mut self) -> io::Result<Vec<(N, Vec<StoredDependency<N>>)>> {
+        let mut nodes = vec![];
+        for entry in self.db.iter() {
+            let (key, value) = entry?;
+            nodes.push((
+                serde_json::from_slice(&key)?,
+                serde_json::from_slice(&value)?,
+            ));
+        }
+        Ok(nodes)
+    }
+
+    fn put(&mut self, name: &N, dependencies: &[StoredDependency<N>]) -> io::Result<()> {
+        self.db
+            .insert(serde_json::to_vec(name)?, serde_json::to_vec(dependencies)?)?;
+        Ok(())
+    }
+
+    fn remove(&mut self, name: &N) -> io::Result<()> {
+        self.db.remove(serde_json::to_vec(name)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use super::*;
+
+fn names(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+fn metadata(key: &str, value: &str) -> Metadata {
+    HashMap::from([(key.to_string(), value.to_string())])
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+fn cascade(entries: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+    entries
+        .iter()
+        .map(|(name, targets)| (name.to_string(), names(targets)))
+        .collect()
+}
+
+fn sorted_cascade(cascade: HashMap<String, Vec<String>>) -> HashMap<String, Vec<String>> {
+    cascade
+        .into_iter()
+        .map(|(name, targets)| (name, sorted(targets)))
+        .collect()
+}
+
+/// `a` depends on `b` and `c`, which are followed by one more level each,
+/// then `d` by a last one.
+fn levels() -> Arc<dyn Lineage<String>> {
+    let lineage = new::<String>();
+    lineage.upsert("a".into(), names(&["b", "c"]));
+    lineage.upsert("b".into(), names(&["d"]));
+    lineage.upsert("c".into(), names(&["e"]));
+    lineage.upsert("d".into(), names(&["f"]));
+    lineage
+}
+
+#[test]
+fn bounded_within_limit() {
+    let bounded = levels().dependencies_cascade_bounded("a".into(), 10);
+    assert!(!bounded.truncated);
+    let expected = cascade(&[
+        ("a", &["b", "c"]),
+        ("b", &["d"]),
+        ("c", &["e"]),
+        ("d", &["f"]),
+    ]);
+    assert_eq!(sorted_cascade(bounded.result), expected);
+}
+
+#[test]
+fn bounded_keeps_whole_levels() {
+    let lineage = levels();
+
+    let bounded = lineage.dependencies_cascade_bounded("a".into(), 4);
+    assert!(bounded.truncated);
+    let expected = cascade(&[("a", &["b", "c"]), ("b", &["d"]), ("c", &["e"])]);
+    assert_eq!(sorted_cascade(bounded.result), expected);
+
+    // the second level doesn't fit whole
+    let bounded = lineage.dependencies_cascade_bounded("a".into(), 3);
+    assert!(bounded.truncated);
+    assert_eq!(
+        sorted_cascade(bounded.result),
+        cascade(&[("a", &["b", "c"])])
+    );
+
+    let bounded = lineage.dependencies_cascade_bounded("a".into(), 0);
+    assert!(bounded.truncated);
+    assert!(bounded.result.is_empty());
+}
+
+#[test]
+fn bounded_through_cycle() {
+    let lineage = new::<String>();
+    lineage.upsert("a".into(), names(&["b"]));
+    lineage.upsert("b".into(), names(&["a"]));
+    let bounded = lineage.dependencies_cascade_bounded("a".into(), 1);
+    assert!(!bounded.truncated);
+    assert_eq!(bounded.result, cascade(&[("a", &["b"]), ("b", &["a"])]));
+}
+
+#[test]
+fn rename_moves_node() {
+    let lineage = new::<String>();
+    lineage.upsert("x".into(), names(&["y"]));
+    lineage.upsert("z".into(), names(&["x"]));
+    lineage.set_metadata("x".into(), metadata("owner", "data"));
+    lineage.set_node_type("x".into(), Some("table".into()));
+    lineage.rename("x".into(), "w".into());
+
+    assert!(!lineage.exists("x".into()));
+    assert_eq!(lineage.dependencies("w".into()), names(&["y"]));
+    assert_eq!(lineage.dependencies("z".into()), names(&["w"]));
+    assert_eq!(
+        lineage.get_metadata("w".into()),
+        Some(metadata("owner", "data"))
+    );
+    assert_eq!(lineage.get_metadata("x".into()), None);
+    assert_eq!(lineage.node_type("w".into()), Some("table".into()));
+    assert_eq!(lineage.node_type("x".into()), None);
+}
+
+#[test]
+fn rename_overwrites_new() {
+    let lineage = new::<String>();
+    // `x` is only ever a dependency, without dependencies of its own
+    lineage.upsert("z".into(), names(&["x"]));
+    lineage.upsert("w".into(), names(&["q"]));
+    lineage.rename("x".into(), "w".into());
+
+    assert!(lineage.dependencies("w".into()).is_empty());
+    assert_eq!(lineage.dependencies("z".into()), names(&["w"]));
+}
+
+#[test]
+fn rename_keeps_soft_delete() {
+    let lineage = new::<String>();
+    lineage.upsert("x".into(), names(&["y"]));
+    lineage.soft_delete("x".into());
+    lineage.rename("x".into(), "w".into());
+
+    assert!(!lineage.exists("w".into()));
+    lineage.restore("w".into());
+    assert_eq!(lineage.dependencies("w".into()), names(&["y"]));
+}
+
+#[test]
+fn merge_unions_dependencies() {
+    let lineage = new::<String>();
+    lineage.upsert("a".into(), names(&["b", "c"]));
+    lineage.upsert("c".into(), names(&["d"]));
+    lineage.upsert("e".into(), names(&["a"]));
+    lineage.set_node_type("a".into(), Some("view".into()));
+    lineage.set_node_type("c".into(), Some("table".into()));
+    lineage.merge("c".into(), "a".into());
+
+    assert!(!lineage.exists("a".into()));
+    // the edge of `a` to `c` itself is dropped
+    assert_eq!(sorted(lineage.dependencies("c".into())), names(&["b", "d"]));
+    assert_eq!(lineage.dependencies("e".into()), names(&["c"]));
+    assert_eq!(lineage.node_type("c".into()), Some("table".into()));
+    assert_eq!(lineage.node_type("a".into()), None);
+}
+
+/// A path of its own for every test, removed when dropped.
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        let file = format!("lineage-{}-{}", std::process::id(), name);
+        TempPath(std::env::temp_dir().join(file))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn change(name: &str, dependencies: &[&str]) -> Change<String> {
+    let dependencies: Vec<Dependency<String>> = dependencies
+        .iter()
+        .map(|d| (d.to_string(), EdgeKind::new(), 1, ALWAYS))
+        .collect();
+    (name.to_string(), Some(dependencies.into()))
+}
+
+#[test]
+fn wal_replay_after_truncation() {
+    let path = TempPath::new("wal");
+    let mut bytes = records::header();
+    records::append(&change("a", &["b"]), &mut bytes);
+    let valid = bytes.len();
+    records::append(&change("c", &["d"]), &mut bytes);
+    // torn by a crash in the middle of the last record
+    bytes.truncate(bytes.len() - 3);
+    std::fs::write(&path.0, &bytes).unwrap();
+
+    let config = Config {
+        wal: Some(path.0.clone()),
+        ..Config::default()
+    };
+    let lineage = with_config::<String>(config.clone());
+    assert_eq!(lineage.dependencies("a".into()), names(&["b"]));
+    assert!(!lineage.exists("c".into()));
+    // cut off, so that appends don't land behind the torn record
+    assert_eq!(std::fs::metadata(&path.0).unwrap().len(), valid as u64);
+
+    lineage.upsert("e".into(), names(&["f"]));
+    assert_eq!(lineage.dependencies("e".into()), names(&["f"]));
+    drop(lineage);
+    let changes: Vec<Change<String>> =
+        records::decode_all(&std::fs::read(&path.0).unwrap()).unwrap();
+    assert_eq!(changes.len(), 2);
+
+    let lineage = with_config::<String>(config);
+    assert_eq!(lineage.dependencies("a".into()), names(&["b"]));
+    assert_eq!(lineage.dependencies("e".into()), names(&["f"]));
+}
@@ -0,0 +1,225 @@
+//! Text formats of the graph, see the `export_` methods of `Lineage`.
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use crate::lineage::{EdgeKind, NodeType, Weight};
+
+/// Graphviz source of `nodes`, each labelled with its name and type, and
+/// of `edges` pointing from each dependency to its dependent. Output is
+/// sorted so that equal graphs render identically.
+pub(crate) fn dot<N: Display>(nodes: Vec<(N, Option<NodeType>)>, edges: Vec<(N, N)>) -> String {
+    let mut nodes: Vec<_> = nodes
+        .into_iter()
+        .map(|(name, node_type)| {
+            let name = name.to_string();
+            let label = match node_type {
+                Some(node_type) => format!("{}\n({})", name, node_type),
+                None => name.clone(),
+            };
+            (name, label)
+        })
+        .collect();
+    nodes.sort();
+    let mut edges: Vec<_> = edges
+        .into_iter()
+        .map(|(from, to)| (from.to_string(), to.to_string()))
+        .collect();
+    edges.sort();
+    edges.dedup();
+
+    let mut out = String::from("digraph lineage {\n    rankdir=LR;\n");
+    for (name, label) in nodes {
+        out += &format!("    {} [label={}];\n", quote(&name), quote(&label));
+    }
+    for (from, to) in edges {
+        out += &format!("    {} -> {};\n", quote(&from), quote(&to));
+    }
+    out += "}\n";
+    out
+}
+
+fn quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// Opening of a GraphML document declaring the node type, edge kind and
+/// weight, and one node attribute per metadata name in `metadata`.
+pub(crate) fn graphml_header(metadata: &BTreeMap<String, usize>) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+        "  <key id=\"type\" for=\"node\" attr.name=\"type\" attr.type=\"string\"/>\n",
+        "  <key id=\"kind\" for=\"edge\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+        "  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"long\"/>\n",
+    ));
+    for (name, i) in metadata {
+        out += &format!(
+            "  <key id=\"m{}\" for=\"node\" attr.name={} attr.type=\"string\"/>\n",
+            i,
+            attribute(name)
+        );
+    }
+    out += "  <graph id=\"lineage\" edgedefault=\"directed\">\n";
+    out
+}
+
+pub(crate) const GRAPHML_FOOTER: &str = "  </graph>\n</graphml>\n";
+
+/// A GraphML node, `metadata` naming its keys as in `graphml_header`.
+pub(crate) fn graphml_node(
+    id: &str,
+    node_type: Option<&NodeType>,
+    metadata: &[(String, String)],
+    keys: &BTreeMap<String, usize>,
+) -> String {
+    if node_type.is_none() && metadata.is_empty() {
+        return format!("    <node id={}/>\n", attribute(id));
+    }
+    let mut out = format!("    <node id={}>\n", attribute(id));
+    if let Some(node_type) = node_type {
+        out += &format!("      <data key=\"type\">{}</data>\n", text(node_type));
+    }
+    for (name, value) in metadata {
+        out += &format!(
+            "      <data key=\"m{}\">{}</data>\n",
+            keys[name],
+            text(value)
+        );
+    }
+    out += "    </node>\n";
+    out
+}
+
+/// A GraphML edge from `source` to `target`.
+pub(crate) fn graphml_edge(source: &str, target: &str, kind: &EdgeKind, weight: Weight) -> String {
+    let mut out = format!(
+        "    <edge source={} target={}>\n",
+        attribute(source),
+        attribute(target)
+    );
+    if !kind.is_empty() {
+        out += &format!("      <data key=\"kind\">{}</data>\n", text(kind));
+    }
+    out += &format!("      <data key=\"weight\">{}</data>\n", weight);
+    out += "    </edge>\n";
+    out
+}
+
+fn text(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn attribute(s: &str) -> String {
+    format!("\"{}\"", text(s).replace('"', "&quot;"))
+}
+
+/// Opening of a GEXF document up to its first node, declaring the node
+/// type and degrees as attributes.
+pub(crate) const GEXF_HEADER: &str = concat!(
+    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+    "<gexf xmlns=\"http://gexf.net/1.2\" xmlns:viz=\"http://gexf.net/1.2/viz\" version=\"1.2\">\n",
+    "  <graph defaultedgetype=\"directed\">\n",
+    "    <attributes class=\"node\">\n",
+    "      <attribute id=\"type\" title=\"type\" type=\"string\"/>\n",
+    "      <attribute id=\"in_degree\" title=\"in_degree\" type=\"integer\"/>\n",
+    "      <attribute id=\"out_degree\" title=\"out_degree\" type=\"integer\"/>\n",
+    "    </attributes>\n",
+    "    <nodes>\n",
+);
+
+pub(crate) const GEXF_EDGES: &str = "    </nodes>\n    <edges>\n";
+
+pub(crate) const GEXF_FOOTER: &str = "    </edges>\n  </graph>\n</gexf>\n";
+
+/// A GEXF node sized by its total degree, so that hubs stand out.
+pub(crate) fn gexf_node(
+    id: &str,
+    node_type: Option<&NodeType>,
+    in_degree: usize,
+    out_degree: usize,
+) -> String {
+    let mut out = format!(
+        "      <node id={} label={}>\n",
+        attribute(id),
+        attribute(id)
+    );
+    out += "        <attvalues>\n";
+    if let Some(node_type) = node_type {
+        out += &format!(
+            "          <attvalue for=\"type\" value={}/>\n",
+            attribute(node_type)
+        );
+    }
+    out += &format!(
+        "          <attvalue for=\"in_degree\" value=\"{}\"/>\n",
+        in_degree
+    );
+    out += &format!(
+        "          <attvalue for=\"out_degree\" value=\"{}\"/>\n",
+        out_degree
+    );
+    out += "        </attvalues>\n";
+    out += &format!(
+        "        <viz:size value=\"{}\"/>\n",
+        1 + in_degree + out_degree
+    );
+    out += "      </node>\n";
+    out
+}
+
+/// The GEXF edge numbered `n`, from `source` to `target`.
+pub(crate) fn gexf_edge(n: usize, source: &str, target: &str, weight: Weight) -> String {
+    format!(
+        "      <edge id=\"{}\" source={} target={} weight=\"{}\"/>\n",
+        n,
+        attribute(source),
+        attribute(target),
+        weight
+    )
+}
+
+/// Turtle describing every node as a PROV entity under `base`, and each
+/// `(name, dependency)` edge as the name being derived from the dependency.
+/// Output is sorted, see `dot`.
+pub(crate) fn turtle<N: Display>(base: &str, nodes: Vec<N>, edges: Vec<(N, N)>) -> String {
+    let mut nodes: Vec<_> = nodes.into_iter().map(|n| iri(base, &n)).collect();
+    nodes.sort();
+    let mut edges: Vec<_> = edges
+        .into_iter()
+        .map(|(name, dependency)| (iri(base, &name), iri(base, &dependency)))
+        .collect();
+    edges.sort();
+    edges.dedup();
+
+    let mut out = String::from("@prefix prov: <http://www.w3.org/ns/prov#> .\n\n");
+    for node in nodes {
+        out += &format!("{} a prov:Entity .\n", node);
+    }
+    for (name, dependency) in edges {
+        out += &format!("{} prov:wasDerivedFrom {} .\n", name, dependency);
+    }
+    out
+}
+
+/// The IRI of `name` under `base`, with every byte of the name other than
+/// the unreserved ones percent-encoded.
+fn iri<N: Display>(base: &str, name: &N) -> String {
+    let mut out = format!("<{}", base);
+    for byte in name.to_string().bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(byte as char)
+            }
+            _ => out += &format!("%{:02X}", byte),
+        }
+    }
+    out.push('>');
+    out
+}
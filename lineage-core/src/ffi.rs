@@ -0,0 +1,79 @@
+//! C API, see `include/differential_lineage.h`. Requests and responses are
+//! the protobuf messages of `proto/lineage.proto`, so that any language
+//! with a protobuf library can embed the engine without a network hop.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+use crate::differential;
+use crate::lineage::Lineage;
+use crate::proto;
+
+/// Opaque to C.
+pub struct LineageHandle(Arc<dyn Lineage<String>>);
+
+/// A new engine, to be released with `lineage_free`.
+#[no_mangle]
+pub extern "C" fn lineage_new() -> *mut LineageHandle {
+    Box::into_raw(Box::new(LineageHandle(differential::new())))
+}
+
+/// Releases an engine of `lineage_new`, null is ignored.
+///
+/// # Safety
+///
+/// `handle` must come from `lineage_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lineage_free(handle: *mut LineageHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Answers the encoded `Request` of `request_len` bytes at `request` with an
+/// encoded `Response`, storing its length in `response_len`. The response
+/// is released with `lineage_buffer_free`. Returns null when the request
+/// doesn't decode or the engine failed.
+///
+/// # Safety
+///
+/// `handle` must be live, `request` readable for `request_len` bytes and
+/// `response_len` writable.
+#[no_mangle]
+pub unsafe extern "C" fn lineage_call(
+    handle: *const LineageHandle,
+    request: *const u8,
+    request_len: usize,
+    response_len: *mut usize,
+) -> *mut u8 {
+    let lineage = &*(*handle).0;
+    let request = if request_len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(request, request_len)
+    };
+    // a panic must not unwind into C
+    let response = catch_unwind(AssertUnwindSafe(|| proto::handle_bytes(lineage, request)));
+    match response {
+        Ok(Ok(response)) => {
+            *response_len = response.len();
+            Box::into_raw(response.into_boxed_slice()) as *mut u8
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Releases a response of `lineage_call`, null is ignored.
+///
+/// # Safety
+///
+/// `buffer` and `len` must be a response and its length as returned by
+/// `lineage_call`, not released before.
+#[no_mangle]
+pub unsafe extern "C" fn lineage_buffer_free(buffer: *mut u8, len: usize) {
+    if !buffer.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buffer, len)));
+    }
+}
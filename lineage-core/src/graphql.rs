@@ -0,0 +1,157 @@
+//! GraphQL schema for lineage explorers. Nested `dependencies` and
+//! `dependents` selections are answered by one bounded-depth query as deep
+//! as the selection, rather than one query per level:
+//!
+//! ```graphql
+//! { node(id: "report") { id dependencies { id dependencies { id } } } }
+//! ```
+//!
+//! Resolvers block on the worker thread.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Lookahead, Object, Schema, SimpleObject,
+};
+
+use crate::lineage::{Direction, Lineage};
+
+pub type LineageSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// A read-only schema answering from `lineage`.
+pub fn schema(lineage: Arc<dyn Lineage<String>>) -> LineageSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(lineage)
+        .finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn node(&self, id: String) -> Node {
+        Node {
+            id,
+            upstream: None,
+            downstream: None,
+        }
+    }
+}
+
+/// A cascade fetched for a selection, with how many hops of it below the
+/// node holding it are complete.
+#[derive(Clone)]
+struct Fetched {
+    cascade: Arc<HashMap<String, Vec<String>>>,
+    depth: usize,
+}
+
+pub struct Node {
+    id: String,
+    upstream: Option<Fetched>,
+    downstream: Option<Fetched>,
+}
+
+#[derive(SimpleObject)]
+struct MetadataEntry {
+    key: String,
+    value: String,
+}
+
+#[Object]
+impl Node {
+    async fn id(&self) -> &str {
+        &self.id
+    }
+
+    async fn node_type(&self, ctx: &Context<'_>) -> Option<String> {
+        lineage(ctx).node_type(self.id.clone())
+    }
+
+    async fn metadata(&self, ctx: &Context<'_>) -> Vec<MetadataEntry> {
+        let mut metadata: Vec<_> = lineage(ctx)
+            .get_metadata(self.id.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(key, value)| MetadataEntry { key, value })
+            .collect();
+        metadata.sort_by(|a, b| a.key.cmp(&b.key));
+        metadata
+    }
+
+    async fn dependencies(&self, ctx: &Context<'_>) -> Vec<Node> {
+        let fetched = self.fetch(ctx, &self.upstream, Direction::Upstream);
+        self.children(fetched, |fetched| Node {
+            id: String::new(),
+            upstream: Some(fetched),
+            downstream: None,
+        })
+    }
+
+    async fn dependents(&self, ctx: &Context<'_>) -> Vec<Node> {
+        let fetched = self.fetch(ctx, &self.downstream, Direction::Downstream);
+        self.children(fetched, |fetched| Node {
+            id: String::new(),
+            upstream: None,
+            downstream: Some(fetched),
+        })
+    }
+}
+
+impl Node {
+    /// The cascade below this node in `direction`, reusing the one fetched
+    /// for an enclosing selection while it still reaches deep enough.
+    fn fetch(&self, ctx: &Context<'_>, held: &Option<Fetched>, direction: Direction) -> Fetched {
+        if let Some(fetched) = held.as_ref().filter(|f| f.depth > 0) {
+            return fetched.clone();
+        }
+        let field = match direction {
+            Direction::Upstream => "dependencies",
+            Direction::Downstream => "dependents",
+        };
+        let depth = 1 + selection_depth(ctx.look_ahead(), field);
+        let lineage = lineage(ctx);
+        let cascade = match direction {
+            Direction::Upstream => lineage.dependencies_k(self.id.clone(), depth),
+            Direction::Downstream => lineage.dependents_k(self.id.clone(), depth),
+        };
+        Fetched {
+            cascade: Arc::new(cascade),
+            depth,
+        }
+    }
+
+    fn children(&self, fetched: Fetched, node: impl Fn(Fetched) -> Node) -> Vec<Node> {
+        let below = Fetched {
+            cascade: fetched.cascade.clone(),
+            depth: fetched.depth - 1,
+        };
+        let mut children: Vec<Node> = fetched
+            .cascade
+            .get(&self.id)
+            .into_iter()
+            .flatten()
+            .map(|id| Node {
+                id: id.clone(),
+                ..node(below.clone())
+            })
+            .collect();
+        children.sort_by(|a, b| a.id.cmp(&b.id));
+        children
+    }
+}
+
+fn lineage<'a>(ctx: &Context<'a>) -> &'a Arc<dyn Lineage<String>> {
+    ctx.data_unchecked::<Arc<dyn Lineage<String>>>()
+}
+
+/// How many levels `field` is nested within itself below `lookahead`.
+fn selection_depth(lookahead: Lookahead, field: &str) -> usize {
+    let next = lookahead.field(field);
+    if next.exists() {
+        1 + selection_depth(next, field)
+    } else {
+        0
+    }
+}
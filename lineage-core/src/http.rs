@@ -0,0 +1,97 @@
+//! HTTP front end with JSON bodies, to run the engine as a lineage
+//! microservice:
+//!
+//! - `GET /nodes/{id}/dependencies?depth=k` and `GET /nodes/{id}/dependents?depth=k`
+//!   answer the cascade, limited to `k` hops when `depth` is given
+//! - `PUT /nodes/{id}` with `{"dependencies": [...]}` upserts the node
+//! - `DELETE /nodes/{id}` deletes it
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use serde::Deserialize;
+
+use crate::lineage::Lineage;
+
+type Shared = Arc<dyn Lineage<String>>;
+
+#[derive(Deserialize)]
+struct Depth {
+    depth: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    dependencies: Vec<String>,
+}
+
+/// The routes of the module documentation, answering from `lineage`.
+pub fn router(lineage: Arc<dyn Lineage<String>>) -> Router {
+    Router::new()
+        .route("/nodes/:id/dependencies", get(dependencies))
+        .route("/nodes/:id/dependents", get(dependents))
+        .route("/nodes/:id", put(upsert).delete(delete))
+        .with_state(lineage)
+}
+
+/// Serves `router` on `addr` until the server fails.
+pub async fn serve(lineage: Arc<dyn Lineage<String>>, addr: SocketAddr) -> hyper::Result<()> {
+    axum::Server::bind(&addr)
+        .serve(router(lineage).into_make_service())
+        .await
+}
+
+async fn dependencies(
+    State(lineage): State<Shared>,
+    Path(id): Path<String>,
+    Query(query): Query<Depth>,
+) -> Result<Json<HashMap<String, Vec<String>>>, StatusCode> {
+    blocking(move || match query.depth {
+        Some(k) => lineage.dependencies_k(id, k),
+        None => lineage.dependencies_cascade(id),
+    })
+    .await
+    .map(Json)
+}
+
+async fn dependents(
+    State(lineage): State<Shared>,
+    Path(id): Path<String>,
+    Query(query): Query<Depth>,
+) -> Result<Json<HashMap<String, Vec<String>>>, StatusCode> {
+    blocking(move || match query.depth {
+        Some(k) => lineage.dependents_k(id, k),
+        None => lineage.dependents_cascade(id),
+    })
+    .await
+    .map(Json)
+}
+
+async fn upsert(
+    State(lineage): State<Shared>,
+    Path(id): Path<String>,
+    Json(node): Json<Node>,
+) -> StatusCode {
+    lineage.upsert(id, node.dependencies);
+    StatusCode::NO_CONTENT
+}
+
+async fn delete(State(lineage): State<Shared>, Path(id): Path<String>) -> StatusCode {
+    lineage.delete(id);
+    StatusCode::NO_CONTENT
+}
+
+/// Runs a query off the async runtime, queries block until the worker
+/// thread answers. Updates only enqueue a message and run in place.
+async fn blocking<T: Send + 'static>(
+    query: impl FnOnce() -> T + Send + 'static,
+) -> Result<T, StatusCode> {
+    tokio::task::spawn_blocking(query)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
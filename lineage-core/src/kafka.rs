@@ -0,0 +1,197 @@
+//! Kafka front end: mutations consumed from one topic, see `consume`, and
+//! edge changes published to another, see `publish`.
+
+use std::time::{Duration, Instant};
+
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
+use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+use rdkafka::Message;
+use serde::{Deserialize, Serialize};
+
+use crate::lineage::Lineage;
+
+/// How long `consume` waits to fill a batch before applying a partial one.
+const BATCH_WINDOW: Duration = Duration::from_millis(100);
+
+/// Encoding of the events of a topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Mutations as `{"op": "upsert", "name": ..., "dependencies": [...]}`
+    /// or `{"op": "delete", "name": ...}`, changes as `{"name": ...,
+    /// "dependency": ..., "time": ..., "diff": ...}`.
+    Json,
+    /// Mutations as a `proto::Request` of kind `upsert` or `delete`,
+    /// changes as a `proto::EdgeChange`.
+    #[cfg(feature = "proto")]
+    Protobuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Event {
+    Upsert {
+        name: String,
+        dependencies: Vec<String>,
+    },
+    Delete {
+        name: String,
+    },
+}
+
+/// An edge insert (`diff` 1) or retraction (`diff` -1) at `time`.
+#[derive(Debug, Serialize)]
+struct Change<'a> {
+    name: &'a str,
+    dependency: &'a str,
+    time: u64,
+    diff: isize,
+}
+
+/// A consumer of `topic` in the consumer group `group_id`. Its offsets are
+/// committed by `consume`, once the events before them are applied.
+pub fn consumer(brokers: &str, group_id: &str, topic: &str) -> KafkaResult<BaseConsumer> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .set("group.id", group_id)
+        .set("enable.auto.commit", "false")
+        .create()?;
+    consumer.subscribe(&[topic])?;
+    Ok(consumer)
+}
+
+/// Applies the events of `consumer` to `lineage` until the consumer fails,
+/// up to `batch_size` at a time. Consecutive upserts go in as one batch,
+/// events that don't decode are skipped. The offsets are committed after
+/// every batch applied, so that a restart resumes from the first event not
+/// yet in `lineage`.
+pub fn consume(
+    lineage: &dyn Lineage<String>,
+    consumer: &BaseConsumer,
+    format: Format,
+    batch_size: usize,
+) -> KafkaResult<()> {
+    loop {
+        let mut events = vec![];
+        let mut polled = false;
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while events.len() < batch_size {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let message = match consumer.poll(timeout) {
+                Some(message) => message?,
+                None => break,
+            };
+            polled = true;
+            match message
+                .payload()
+                .and_then(|payload| decode(format, payload))
+            {
+                Some(event) => events.push(event),
+                None => tracing::warn!(offset = message.offset(), "skipping undecodable event"),
+            }
+        }
+        if !polled {
+            continue;
+        }
+        apply(lineage, events);
+        // answered once the updates queued before it are handled
+        lineage.now();
+        consumer.commit_consumer_state(CommitMode::Sync)?;
+    }
+}
+
+fn decode(format: Format, payload: &[u8]) -> Option<Event> {
+    match format {
+        Format::Json => serde_json::from_slice(payload).ok(),
+        #[cfg(feature = "proto")]
+        Format::Protobuf => {
+            use crate::proto::request::Kind;
+            use prost::Message;
+
+            match crate::proto::Request::decode(payload).ok()?.kind? {
+                Kind::Upsert(r) => Some(Event::Upsert {
+                    name: r.name,
+                    dependencies: r.dependencies,
+                }),
+                Kind::Delete(r) => Some(Event::Delete { name: r.name }),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn apply(lineage: &dyn Lineage<String>, events: Vec<Event>) {
+    let mut batch = vec![];
+    for event in events {
+        match event {
+            Event::Upsert { name, dependencies } => batch.push((name, dependencies)),
+            Event::Delete { name } => {
+                if !batch.is_empty() {
+                    lineage.upsert_batch(std::mem::take(&mut batch));
+                }
+                lineage.delete(name);
+            }
+        }
+    }
+    if !batch.is_empty() {
+        lineage.upsert_batch(batch);
+    }
+}
+
+/// A producer to the cluster at `brokers`.
+pub fn producer(brokers: &str) -> KafkaResult<BaseProducer> {
+    ClientConfig::new()
+        .set("bootstrap.servers", brokers)
+        .create()
+}
+
+/// Produces every edge change of `lineage` to `topic`, beginning with the
+/// current edges as inserts, until `lineage` is dropped. Changes are keyed
+/// by the name of the edge so that those of one node stay in order.
+pub fn publish(
+    lineage: &dyn Lineage<String>,
+    producer: &BaseProducer,
+    topic: &str,
+    format: Format,
+) -> KafkaResult<()> {
+    for (name, dependency, diff, time) in lineage.changes() {
+        let payload = match format {
+            Format::Json => {
+                let change = Change {
+                    name: &name,
+                    dependency: &dependency,
+                    time,
+                    diff,
+                };
+                serde_json::to_vec(&change).unwrap()
+            }
+            #[cfg(feature = "proto")]
+            Format::Protobuf => {
+                use prost::Message;
+
+                let change = crate::proto::EdgeChange {
+                    name: name.clone(),
+                    dependency,
+                    time,
+                    diff: diff as i64,
+                };
+                change.encode_to_vec()
+            }
+        };
+        let mut record = BaseRecord::to(topic).key(&name).payload(&payload);
+        loop {
+            match producer.send(record) {
+                Ok(()) => break,
+                // wait for deliveries to make room in the queue
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), r)) => {
+                    producer.poll(Duration::from_millis(100));
+                    record = r;
+                }
+                Err((err, _)) => return Err(err),
+            }
+        }
+        producer.poll(Duration::ZERO);
+    }
+    producer.flush(Duration::from_secs(10))
+}
@@ -0,0 +1,31 @@
+#[cfg(feature = "arrow")]
+pub mod columnar;
+#[cfg(feature = "dbt")]
+mod dbt;
+#[cfg(feature = "engine")]
+pub mod differential;
+mod export;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod lineage;
+#[cfg(feature = "proto")]
+pub mod proto;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "sql")]
+pub mod sql;
+#[cfg(any(feature = "websocket", all(unix, feature = "uds")))]
+mod subscriptions;
+pub mod telemetry;
+#[cfg(all(unix, feature = "uds"))]
+pub mod uds;
+#[cfg(feature = "websocket")]
+pub mod websocket;
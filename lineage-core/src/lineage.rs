@@ -0,0 +1,836 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::hash::Hash;
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use crossbeam::channel::{Receiver, TryRecvError};
+#[cfg(feature = "json")]
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[cfg(feature = "parquet")]
+use crate::columnar;
+#[cfg(feature = "dbt")]
+use crate::dbt;
+use crate::export;
+
+pub type Name = u64;
+pub type Metadata = HashMap<String, String>;
+/// Label of a dependency edge, e.g. "reads" or "derives". Edges added by
+/// `upsert` carry the empty label.
+pub type EdgeKind = String;
+/// Cost of a dependency edge, e.g. data volume or latency. Edges added
+/// without a weight cost 1.
+pub type Weight = u64;
+/// Type tag of a node, e.g. "table", "view" or "report".
+pub type NodeType = String;
+/// Logical time of the graph, advanced by every update and query.
+pub type Timestamp = u64;
+/// Application time at which an edge holds, e.g. seconds since the epoch,
+/// as opposed to the system `Timestamp` at which it was recorded.
+pub type ValidTime = u64;
+/// Edge changes at one time, `1` for an added edge and `-1` for a removed
+/// one.
+pub type ChangeBatch<N> = Vec<((N, N), Timestamp, isize)>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error<N> {
+    /// The upsert of the contained name was rejected because it would have
+    /// closed a dependency cycle.
+    Cycle(N),
+    /// The delete of the contained name was rejected because other nodes
+    /// still depend on it.
+    HasDependents(N),
+}
+
+impl<N: fmt::Debug> fmt::Display for Error<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Cycle(name) => write!(f, "upsert of {:?} would create a cycle", name),
+            Error::HasDependents(name) => write!(f, "{:?} still has dependents", name),
+        }
+    }
+}
+
+impl<N: fmt::Debug> std::error::Error for Error<N> {}
+
+/// Both cascades of a node, see `Lineage::impact` and `Lineage::neighborhood`.
+#[derive(Clone, Debug)]
+pub struct Impact<N> {
+    pub upstream: HashMap<N, Vec<N>>,
+    pub downstream: HashMap<N, Vec<N>>,
+}
+
+/// Summary of the whole graph, see `Lineage::stats`. Degrees count distinct
+/// edges, in degree being the number of dependents.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub nodes: usize,
+    pub edges: usize,
+    pub max_in_degree: usize,
+    pub max_out_degree: usize,
+    pub roots: usize,
+    pub leaves: usize,
+    pub has_cycle: bool,
+}
+
+/// Size of the backend's traces, see `Lineage::compaction_stats`. Updates
+/// count every `(key, value, time, diff)` tuple across all batches.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    pub traces: usize,
+    pub batches: usize,
+    pub updates: usize,
+}
+
+/// A result that may have been cut short to respect a size limit.
+#[derive(Clone, Debug)]
+pub struct Bounded<T> {
+    pub result: T,
+    pub truncated: bool,
+}
+
+/// The graph with every strongly connected component collapsed into a single
+/// node, see `Lineage::condensation`.
+#[derive(Clone, Debug)]
+pub struct Condensation<N> {
+    pub components: Vec<HashSet<N>>,
+    /// `(dependent, dependency)` edges between indices into `components`.
+    pub edges: Vec<(usize, usize)>,
+}
+
+/// Edges gained and lost between two times, see `Lineage::diff`.
+#[derive(Clone, Debug)]
+pub struct EdgeDiff<N> {
+    pub added: Vec<(N, N)>,
+    pub removed: Vec<(N, N)>,
+}
+
+/// Who made a change and why, see `Lineage::upsert_by`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Attribution {
+    pub actor: String,
+    pub reason: String,
+}
+
+/// An attributed change of a node, see `Lineage::audit`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEntry<N> {
+    pub time: Timestamp,
+    pub actor: String,
+    pub reason: String,
+    /// The dependencies set by the change, `None` for a delete.
+    pub dependencies: Option<Vec<N>>,
+}
+
+/// The whole graph as stored by `Lineage::to_json`.
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GraphDocument<N> {
+    pub nodes: Vec<NodeDocument<N>>,
+}
+
+/// An upserted node, see `GraphDocument`. Nodes only referenced as a
+/// dependency have no entry of their own.
+#[cfg(feature = "json")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeDocument<N> {
+    pub name: N,
+    pub dependencies: Vec<N>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_type: Option<NodeType>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Which way from a node a query follows the edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Towards the dependencies.
+    Upstream,
+    /// Towards the dependents.
+    Downstream,
+}
+
+/// A query kept installed by `Lineage::register_query`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuerySpec<N> {
+    DependenciesCascade(N),
+    DependentsCascade(N),
+}
+
+/// Identifies a registered query.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct QueryHandle(pub(crate) usize);
+
+/// Where a cascade stops expanding, see `Lineage::dependencies_cascade_until`.
+/// Stop nodes are still part of the result, only their own edges are not
+/// followed.
+#[derive(Clone)]
+pub struct Stop<N> {
+    pub nodes: Vec<N>,
+    pub metadata: Option<Arc<dyn Fn(&Metadata) -> bool + Send + Sync>>,
+}
+
+impl<N> Stop<N> {
+    pub fn at_nodes(nodes: Vec<N>) -> Self {
+        Stop {
+            nodes,
+            metadata: None,
+        }
+    }
+
+    pub fn at_metadata(predicate: impl Fn(&Metadata) -> bool + Send + Sync + 'static) -> Self {
+        Stop {
+            nodes: vec![],
+            metadata: Some(Arc::new(predicate)),
+        }
+    }
+}
+
+/// The answer to a call, resolved once the backend has sent it, see
+/// `Graph::dependencies_async`.
+pub struct Reply<T> {
+    ready: Option<T>,
+    rx: Option<Receiver<T>>,
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+/// Wakes the task awaiting a `Reply` after the answer has been sent.
+#[derive(Clone)]
+pub(crate) struct Wake(Arc<Mutex<Option<Waker>>>);
+
+impl<T> Reply<T> {
+    /// An answer already at hand.
+    pub fn ready(value: T) -> Self {
+        Reply {
+            ready: Some(value),
+            rx: None,
+            waker: Arc::default(),
+        }
+    }
+
+    /// An answer yet to arrive on `rx`.
+    #[cfg_attr(not(feature = "engine"), allow(dead_code))]
+    pub(crate) fn pending(rx: Receiver<T>) -> (Self, Wake) {
+        let waker = Arc::default();
+        let wake = Wake(Arc::clone(&waker));
+        let reply = Reply {
+            ready: None,
+            rx: Some(rx),
+            waker,
+        };
+        (reply, wake)
+    }
+}
+
+#[cfg_attr(not(feature = "engine"), allow(dead_code))]
+impl Wake {
+    pub(crate) fn wake(&self) {
+        if let Some(waker) = self.0.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+// never pinned structurally, the answer is only ever moved out
+impl<T> Unpin for Reply<T> {}
+
+impl<T> Future for Reply<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        if let Some(value) = self.ready.take() {
+            return Poll::Ready(value);
+        }
+        // registered before looking, so an answer sent in between still wakes
+        *self.waker.lock().unwrap() = Some(cx.waker().clone());
+        let rx = self.rx.as_ref().expect("reply polled after completion");
+        match rx.try_recv() {
+            Ok(value) => Poll::Ready(value),
+            Err(TryRecvError::Empty) => Poll::Pending,
+            Err(TryRecvError::Disconnected) => panic!("the backend dropped the call"),
+        }
+    }
+}
+
+/// The part of `Lineage` every backend offers, the remote client of
+/// `lineage-client` included: nodes and edges, direct, transitive and k-hop
+/// lookups, the change feeds, metadata and the plain updates. Code written
+/// against `dyn Graph` runs unchanged in process or behind a server.
+pub trait Graph<N = Name>: Send + Sync {
+    // query
+    /// Whether `name` has been upserted and not deleted since.
+    fn exists(&self, name: N) -> bool;
+    /// Every upserted node and every node referenced as a dependency.
+    fn nodes(&self) -> Vec<N>;
+    /// Every `(name, dependency)` edge.
+    fn edges(&self) -> Vec<(N, N)>;
+    /// Nodes without dependencies.
+    fn roots(&self) -> Vec<N>;
+    /// Nodes without dependents.
+    fn leaves(&self) -> Vec<N>;
+    /// Nodes with neither dependencies nor dependents.
+    fn orphans(&self) -> Vec<N>;
+    fn dependencies(&self, name: N) -> Vec<N>;
+    fn dependents(&self, name: N) -> Vec<N>;
+    fn dependencies_cascade(&self, name: N) -> HashMap<N, Vec<N>>;
+    fn dependents_cascade(&self, name: N) -> HashMap<N, Vec<N>>;
+    fn dependencies_count(&self, name: N) -> usize;
+    fn dependents_count(&self, name: N) -> usize;
+    /// `dependencies_cascade` and `dependents_cascade` in one round trip.
+    fn impact(&self, name: N) -> Impact<N>;
+    fn dependencies_k(&self, name: N, k: usize) -> HashMap<N, Vec<N>>;
+    fn dependents_k(&self, name: N, k: usize) -> HashMap<N, Vec<N>>;
+    /// `dependencies_k` and `dependents_k` in one round trip.
+    fn neighborhood(&self, name: N, k: usize) -> Impact<N>;
+    // sorted variants, for byte-identical output across identical queries
+    fn dependencies_cascade_sorted(&self, name: N) -> BTreeMap<N, Vec<N>>
+    where
+        N: Ord,
+    {
+        sorted(self.dependencies_cascade(name))
+    }
+    fn dependents_cascade_sorted(&self, name: N) -> BTreeMap<N, Vec<N>>
+    where
+        N: Ord,
+    {
+        sorted(self.dependents_cascade(name))
+    }
+    fn dependencies_k_sorted(&self, name: N, k: usize) -> BTreeMap<N, Vec<N>>
+    where
+        N: Ord,
+    {
+        sorted(self.dependencies_k(name, k))
+    }
+    fn dependents_k_sorted(&self, name: N, k: usize) -> BTreeMap<N, Vec<N>>
+    where
+        N: Ord,
+    {
+        sorted(self.dependents_k(name, k))
+    }
+    /// Changes to the edges of `dependents_cascade(name)` as they happen,
+    /// beginning with the current edges as additions.
+    fn subscribe_dependents_cascade(&self, name: N) -> Receiver<ChangeBatch<N>>;
+    /// Every `(name, dependency)` edge insert (`1`) and retraction (`-1`)
+    /// with the time it took effect, beginning with the current edges.
+    fn changes(&self) -> Receiver<(N, N, isize, Timestamp)>;
+    fn get_metadata(&self, name: N) -> Option<Metadata>;
+    // async variants, for callers on an async runtime that must not block
+    // their thread on the answer. By default the answer is computed before
+    // returning, like the plain query.
+    fn exists_async(&self, name: N) -> Reply<bool> {
+        Reply::ready(self.exists(name))
+    }
+    fn dependencies_async(&self, name: N) -> Reply<Vec<N>> {
+        Reply::ready(self.dependencies(name))
+    }
+    fn dependents_async(&self, name: N) -> Reply<Vec<N>> {
+        Reply::ready(self.dependents(name))
+    }
+    fn dependencies_cascade_async(&self, name: N) -> Reply<HashMap<N, Vec<N>>> {
+        Reply::ready(self.dependencies_cascade(name))
+    }
+    fn dependents_cascade_async(&self, name: N) -> Reply<HashMap<N, Vec<N>>> {
+        Reply::ready(self.dependents_cascade(name))
+    }
+    fn dependencies_k_async(&self, name: N, k: usize) -> Reply<HashMap<N, Vec<N>>> {
+        Reply::ready(self.dependencies_k(name, k))
+    }
+    fn dependents_k_async(&self, name: N, k: usize) -> Reply<HashMap<N, Vec<N>>> {
+        Reply::ready(self.dependents_k(name, k))
+    }
+    // update
+    fn upsert(&self, name: N, dependencies: Vec<N>);
+    fn upsert_batch(&self, batch: Vec<(N, Vec<N>)>);
+    fn add_dependency(&self, name: N, dependency: N);
+    fn remove_dependency(&self, name: N, dependency: N);
+    fn delete(&self, name: N);
+    /// Replaces the metadata of `name`, an empty map clears it.
+    fn set_metadata(&self, name: N, metadata: Metadata);
+}
+
+/// Shared across threads, e.g. by the network front ends.
+pub trait Lineage<N = Name>: Graph<N> {
+    // query
+    /// Nodes ordered so that every node comes after its dependencies. Nodes
+    /// on a cycle, or depending on one, are left out.
+    fn topological_order(&self) -> Vec<N>;
+    /// Like `topological_order`, restricted to `name` and its dependencies.
+    fn topological_order_of(&self, name: N) -> Vec<N>;
+    fn has_cycle(&self) -> bool;
+    /// Size and shape of the whole graph in one round trip.
+    fn stats(&self) -> Stats;
+    /// Every node connected to `name` when edge direction is ignored,
+    /// including `name` itself.
+    fn component(&self, name: N) -> HashSet<N>;
+    /// The weakly connected components of the whole graph.
+    fn components(&self) -> Vec<HashSet<N>>;
+    /// Clusters of mutually dependent nodes. A node on no cycle forms a
+    /// component of its own.
+    fn strongly_connected_components(&self) -> Vec<HashSet<N>>;
+    /// The acyclic graph of `strongly_connected_components`.
+    fn condensation(&self) -> Condensation<N>;
+    /// The fewest `(name, dependency)` edges with the same reachability as
+    /// `edges`. Only minimal on acyclic graphs, edges on a cycle are dropped.
+    fn transitive_reduction(&self) -> Vec<(N, N)>;
+    /// The other nodes sharing at least one direct dependency with `name`.
+    fn siblings(&self, name: N) -> Vec<N>;
+    /// The union of the cascades of every name in `names`, built in a single
+    /// dataflow.
+    fn dependencies_cascade_many(&self, names: Vec<N>) -> HashMap<N, Vec<N>>;
+    fn dependents_cascade_many(&self, names: Vec<N>) -> HashMap<N, Vec<N>>;
+    fn dependencies_closure(&self, name: N) -> HashSet<N>;
+    fn dependents_closure(&self, name: N) -> HashSet<N>;
+    /// Number of distinct nodes in `dependencies_closure`.
+    fn dependencies_cascade_count(&self, name: N) -> usize;
+    /// Number of distinct nodes in `dependents_closure`.
+    fn dependents_cascade_count(&self, name: N) -> usize;
+    /// The `n` nodes with the most direct dependents, busiest first.
+    fn top_dependents(&self, n: usize) -> Vec<(N, usize)>;
+    fn common_dependencies(&self, a: N, b: N) -> HashSet<N>;
+    fn common_dependents(&self, a: N, b: N) -> HashSet<N>;
+    /// The nodes every one of `names` depends on, directly or transitively.
+    fn shared_upstream(&self, names: Vec<N>) -> HashSet<N>;
+    /// The common dependencies of `a` and `b` that no other common
+    /// dependency depends on, i.e. where their lineages first meet.
+    fn lowest_common_dependencies(&self, a: N, b: N) -> HashSet<N>;
+    /// The dependencies reachable from `a` but not from `b`, and those
+    /// reachable from `b` but not from `a`.
+    fn diff_dependencies(&self, a: N, b: N) -> (HashSet<N>, HashSet<N>);
+    /// The `(name, dependency)` edges lying on some path from any of
+    /// `sources` down to any of `sinks`, sinks depending on sources.
+    fn subgraph_between(&self, sources: Vec<N>, sinks: Vec<N>) -> Vec<(N, N)>;
+    /// Every simple path of at most `max_depth` edges from `from` down its
+    /// dependencies to `to`.
+    fn all_paths(&self, from: N, to: N, max_depth: usize) -> Vec<Vec<N>>;
+    /// Whether `b` depends on `a`, directly or transitively. Cheaper than
+    /// any cascade, the search stops as soon as `a` is found.
+    fn is_upstream_of(&self, a: N, b: N) -> bool;
+    /// Minimum number of dependency hops from `from` to `to`.
+    fn distance(&self, from: N, to: N) -> Option<usize>;
+    /// The dependency path from `from` to `to` with the lowest total weight.
+    fn cheapest_path(&self, from: N, to: N) -> Option<(Weight, Vec<N>)>;
+    /// Number of edges on the longest dependency chain below `name`, `None`
+    /// when `name` is unknown or depends on a cycle.
+    fn max_depth(&self, name: N) -> Option<usize>;
+    /// One dependency chain of `max_depth` edges, starting at `name`.
+    fn critical_path(&self, name: N) -> Option<Vec<N>>;
+    /// The dependencies of `sink` lying on every path from `sink` to its
+    /// roots, so that losing any one of them cuts `sink` off entirely.
+    fn critical_nodes(&self, sink: N) -> HashSet<N>;
+    /// The nodes whose shortest dependency path from `name` is exactly
+    /// `depth` hops long.
+    fn dependencies_at_depth(&self, name: N, depth: usize) -> Vec<N>;
+    fn dependents_at_depth(&self, name: N, depth: usize) -> Vec<N>;
+    // sorted variants, for byte-identical output across identical queries
+    fn dependencies_closure_sorted(&self, name: N) -> BTreeSet<N>
+    where
+        N: Ord,
+    {
+        self.dependencies_closure(name).into_iter().collect()
+    }
+    fn dependents_closure_sorted(&self, name: N) -> BTreeSet<N>
+    where
+        N: Ord,
+    {
+        self.dependents_closure(name).into_iter().collect()
+    }
+    fn dependencies_cascade_until(&self, name: N, stop: Stop<N>) -> HashMap<N, Vec<N>>;
+    fn dependents_cascade_until(&self, name: N, stop: Stop<N>) -> HashMap<N, Vec<N>>;
+    /// The cascade over the graph with every node of `exclude` removed, so
+    /// nodes only reachable through an excluded one are left out as well.
+    fn dependencies_cascade_excluding(&self, name: N, exclude: Vec<N>) -> HashMap<N, Vec<N>>;
+    fn dependents_cascade_excluding(&self, name: N, exclude: Vec<N>) -> HashMap<N, Vec<N>>;
+    /// The cascade limited to whole depth levels reaching at most `max_nodes`
+    /// nodes, flagged as truncated when nodes were left out.
+    fn dependencies_cascade_bounded(
+        &self,
+        name: N,
+        max_nodes: usize,
+    ) -> Bounded<HashMap<N, Vec<N>>>;
+    fn dependents_cascade_bounded(&self, name: N, max_nodes: usize) -> Bounded<HashMap<N, Vec<N>>>;
+    /// The cascade delivered in batches of at most `batch_size` entries, the
+    /// channel closes after the last batch.
+    fn dependencies_cascade_stream(&self, name: N, batch_size: usize)
+        -> Receiver<Vec<(N, Vec<N>)>>;
+    fn dependents_cascade_stream(&self, name: N, batch_size: usize) -> Receiver<Vec<(N, Vec<N>)>>;
+    /// Installs a query whose result is maintained incrementally until it is
+    /// released or the backend is dropped, instead of being computed from
+    /// scratch per call.
+    fn register_query(&self, spec: QuerySpec<N>) -> QueryHandle;
+    /// The current result of a registered query, empty for a handle this
+    /// backend didn't hand out.
+    fn poll_query(&self, handle: QueryHandle) -> HashMap<N, Vec<N>>;
+    /// The current result of a registered query as additions, followed by
+    /// every later change. The receiver of a handle this backend didn't hand
+    /// out is closed right away.
+    fn subscribe_query(&self, handle: QueryHandle) -> Receiver<ChangeBatch<N>>;
+    /// Uninstalls a registered query, closing the receivers of its
+    /// subscribers. The handle is unknown from then on.
+    fn release_query(&self, handle: QueryHandle);
+    /// The attributed changes of `name`, oldest first.
+    fn audit(&self, name: N) -> Vec<AuditEntry<N>>;
+    /// Every node of the cascade with its hop distance from `name` and its
+    /// own dependencies, empty for the nodes the cascade ends at.
+    fn dependencies_cascade_with_depth(&self, name: N) -> HashMap<N, (usize, Vec<N>)>;
+    fn dependents_cascade_with_depth(&self, name: N) -> HashMap<N, (usize, Vec<N>)>;
+    fn dependencies_cascade_with_metadata(
+        &self,
+        name: N,
+    ) -> (HashMap<N, Vec<N>>, HashMap<N, Metadata>);
+    fn dependents_cascade_with_metadata(
+        &self,
+        name: N,
+    ) -> (HashMap<N, Vec<N>>, HashMap<N, Metadata>);
+    /// The current logical time, to be passed to the `_at` queries later.
+    fn now(&self) -> Timestamp;
+    /// `dependencies` as of `time`. Times older than the retained history
+    /// read the oldest state still available.
+    fn dependencies_at(&self, name: N, time: Timestamp) -> Vec<N>;
+    /// `dependents_cascade` as of `time`, see `dependencies_at`.
+    fn dependents_cascade_at(&self, name: N, time: Timestamp) -> HashMap<N, Vec<N>>;
+    /// The edges of `dependencies_cascade(name)` added and removed between
+    /// the times `from` and `to`.
+    fn diff(&self, name: N, from: Timestamp, to: Timestamp) -> EdgeDiff<N>;
+    /// The cascade over the edges whose validity contains `valid`, as the
+    /// graph stood at system time `time`.
+    fn dependencies_cascade_bitemporal(
+        &self,
+        name: N,
+        valid: ValidTime,
+        time: Timestamp,
+    ) -> HashMap<N, Vec<N>>;
+    /// `dependencies_cascade_bitemporal` as the graph stands now.
+    fn dependencies_cascade_valid_at(&self, name: N, valid: ValidTime) -> HashMap<N, Vec<N>> {
+        let time = self.now();
+        self.dependencies_cascade_bitemporal(name, valid, time)
+    }
+    /// When the `(from, to)` edge was added (`1`) and removed (`-1`), as far
+    /// back as the retained history goes.
+    fn edge_history(&self, from: N, to: N) -> Vec<(Timestamp, isize)>;
+    /// When the dependencies of `name` last changed. Changes older than the
+    /// retained history report the oldest retained time.
+    fn last_modified(&self, name: N) -> Option<Timestamp>;
+    /// Every node whose dependencies changed at or after `time`, see
+    /// `last_modified`.
+    fn changed_since(&self, time: Timestamp) -> Vec<N>;
+    /// Labels the current time, see `snapshot`. Compaction keeps every
+    /// labelled time readable.
+    fn tag_snapshot(&self, label: &str) -> Timestamp;
+    /// The time labelled by `tag_snapshot`.
+    fn snapshot(&self, label: &str) -> Option<Timestamp>;
+    /// `dependencies_at` the time labelled `label`.
+    fn dependencies_at_tag(&self, name: N, label: &str) -> Option<Vec<N>> {
+        let time = self.snapshot(label)?;
+        Some(self.dependencies_at(name, time))
+    }
+    /// `dependents_cascade_at` the time labelled `label`.
+    fn dependents_cascade_at_tag(&self, name: N, label: &str) -> Option<HashMap<N, Vec<N>>> {
+        let time = self.snapshot(label)?;
+        Some(self.dependents_cascade_at(name, time))
+    }
+    fn node_type(&self, name: N) -> Option<NodeType>;
+    /// Cascade that only reaches, and only traverses through, nodes whose
+    /// type is one of `types`. Untyped nodes are never reached.
+    fn dependencies_cascade_filtered(&self, name: N, types: &[NodeType]) -> HashMap<N, Vec<N>>;
+    fn dependents_cascade_filtered(&self, name: N, types: &[NodeType]) -> HashMap<N, Vec<N>>;
+    fn dependencies_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> Vec<N>;
+    fn dependents_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> Vec<N>;
+    fn dependencies_cascade_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> HashMap<N, Vec<N>>;
+    fn dependents_cascade_of_kind(&self, name: N, kinds: Vec<EdgeKind>) -> HashMap<N, Vec<N>>;
+    // update
+    /// Like `upsert`, applied at the caller's `time`, e.g. when replaying
+    /// catalog events. Times before `now` are applied at `now` instead.
+    fn upsert_at(&self, name: N, dependencies: Vec<N>, time: Timestamp);
+    /// Like `upsert`, deleting `name` again once `ttl` has passed unless it
+    /// is updated or deleted before then.
+    fn upsert_with_ttl(&self, name: N, dependencies: Vec<N>, ttl: Duration);
+    fn upsert_typed(&self, name: N, dependencies: Vec<(N, EdgeKind)>);
+    fn upsert_weighted(&self, name: N, dependencies: Vec<(N, Weight)>);
+    /// Like `upsert`, each edge only holding over its valid-time range.
+    /// Edges upserted any other way are valid at all times.
+    fn upsert_valid(&self, name: N, dependencies: Vec<(N, Range<ValidTime>)>);
+    /// Like `upsert`, but reports whether the backend rejected the update.
+    fn try_upsert(&self, name: N, dependencies: Vec<N>) -> Result<(), Error<N>>;
+    /// Like `delete`, applied at the caller's `time`, see `upsert_at`.
+    fn delete_at(&self, name: N, time: Timestamp);
+    /// Like `upsert`, recording who made the change and why, see `audit`.
+    fn upsert_by(&self, name: N, dependencies: Vec<N>, by: Attribution);
+    /// Like `delete`, recording who made the change and why, see `audit`.
+    fn delete_by(&self, name: N, by: Attribution);
+    /// Like `delete`, but reports whether the backend rejected the delete.
+    fn try_delete(&self, name: N) -> Result<(), Error<N>>;
+    /// Undoes the dependency changes of the latest `n` mutations, as far as
+    /// the backend journals them.
+    fn rollback(&self, n: usize);
+    /// Hides `name` and the edges pointing at it from every query, keeping
+    /// them stored until `restore`.
+    fn soft_delete(&self, name: N);
+    /// Undoes `soft_delete`.
+    fn restore(&self, name: N);
+    /// Makes `alias` another name for `target`: queries given `alias` answer
+    /// for `target`. Results and updates always use the target's own name.
+    fn alias(&self, alias: N, target: N);
+    /// Atomically renames `old` to `new`, keeping its dependencies, metadata,
+    /// type and every edge pointing at it. A soft-deleted `old` stays so as
+    /// `new`. An existing `new` is overwritten.
+    fn rename(&self, old: N, new: N);
+    /// Atomically folds `from` into `into`: the dependency lists and metadata
+    /// are unioned, the type of `into` is kept if it has one, and every
+    /// dependent of `from` now depends on `into`.
+    fn merge(&self, into: N, from: N);
+    /// Sets or, with `None`, clears the type tag of `name`.
+    fn set_node_type(&self, name: N, node_type: Option<NodeType>);
+    // admin
+    /// Compacts every trace down to the retained history right away, e.g.
+    /// after a bulk load, rather than as queries happen to touch them.
+    fn compact(&self);
+    /// How much the backend's traces currently hold, see `compact`.
+    fn compaction_stats(&self) -> CompactionStats;
+    /// Writes every node and its dependencies to `path`, to be restored by
+    /// the backend's constructor on the next start.
+    fn save_snapshot(&self, path: &Path) -> io::Result<()>;
+    /// Writes the nodes whose dependencies changed at or after `time` to
+    /// `path`, a deleted node as such, for `apply_backup` to replay on top of
+    /// an older snapshot or backup. Times older than the retained history
+    /// back up every node.
+    fn backup_since(&self, time: Timestamp, path: &Path) -> io::Result<()>;
+    /// Replays a backup written by `backup_since`, in one batch. A file that
+    /// is not a complete backup is `InvalidData` and applies nothing.
+    fn apply_backup(&self, path: &Path) -> io::Result<()>;
+    // export
+    /// Graphviz source of the whole graph or, given a `root`, of its cascade
+    /// in `direction`. Edges point from dependency to dependent, the way
+    /// data flows, and nodes are labelled with their name and type.
+    fn export_dot(&self, root: Option<N>, direction: Direction) -> String
+    where
+        N: fmt::Display + Clone + Eq + Hash,
+    {
+        let (nodes, edges): (Vec<N>, Vec<(N, N)>) = match root {
+            None => {
+                let edges = self.edges().into_iter().map(|(n, d)| (d, n)).collect();
+                (self.nodes(), edges)
+            }
+            Some(root) => {
+                let edges: Vec<(N, N)> = match direction {
+                    Direction::Upstream => self
+                        .dependencies_cascade(root.clone())
+                        .into_iter()
+                        .flat_map(|(n, ds)| ds.into_iter().map(move |d| (d, n.clone())))
+                        .collect(),
+                    Direction::Downstream => self
+                        .dependents_cascade(root.clone())
+                        .into_iter()
+                        .flat_map(|(n, ds)| ds.into_iter().map(move |d| (n.clone(), d)))
+                        .collect(),
+                };
+                let mut nodes: HashSet<N> = HashSet::new();
+                nodes.insert(root);
+                for (from, to) in &edges {
+                    nodes.insert(from.clone());
+                    nodes.insert(to.clone());
+                }
+                (nodes.into_iter().collect(), edges)
+            }
+        };
+        let nodes = nodes
+            .into_iter()
+            .map(|n| {
+                let node_type = self.node_type(n.clone());
+                (n, node_type)
+            })
+            .collect();
+        export::dot(nodes, edges)
+    }
+    /// GraphML of the whole graph, e.g. for yEd. Nodes carry their type and
+    /// metadata, edges point from dependency to dependent and carry their
+    /// kind and weight.
+    fn export_graphml(&self) -> String
+    where
+        N: fmt::Display;
+    /// Turtle of the whole graph in the W3C PROV-O vocabulary: every node is
+    /// a `prov:Entity` named by its name appended to `base`, e.g.
+    /// `https://example.com/lineage/`, and every edge a `prov:wasDerivedFrom`
+    /// from the node to its dependency.
+    fn export_turtle(&self, base: &str) -> String
+    where
+        N: fmt::Display,
+    {
+        export::turtle(base, self.nodes(), self.edges())
+    }
+    /// Writes every `(name, dependency)` edge with the `last_modified` time
+    /// of `name` to a Parquet file at `path`, see `columnar::edges_schema`.
+    #[cfg(feature = "parquet")]
+    fn export_parquet(&self, path: &Path) -> Result<(), parquet::errors::ParquetError>
+    where
+        N: fmt::Display + Clone + Ord,
+    {
+        let mut edges = self.edges();
+        edges.sort();
+        let mut rows = Vec::with_capacity(edges.len());
+        let mut last: Option<(N, Option<Timestamp>)> = None;
+        for (name, dependency) in edges {
+            let last_modified = match &last {
+                Some((n, time)) if *n == name => *time,
+                _ => self.last_modified(name.clone()),
+            };
+            last = Some((name.clone(), last_modified));
+            rows.push((name.to_string(), dependency.to_string(), last_modified));
+        }
+        let batch = columnar::edges_batch(rows)?;
+        columnar::write_parquet(path, &batch)
+    }
+    /// GEXF of the whole graph for Gephi, edges pointing from dependency to
+    /// dependent. Nodes carry their type and degrees and are sized by their
+    /// total degree.
+    fn export_gexf(&self) -> String
+    where
+        N: fmt::Display;
+    /// Loads `parent,child` lines, each child depending on its parents, as
+    /// one batch. The dependencies of every child are replaced by the
+    /// parents listed for it. Fields are not quoted, a `parent,child` header
+    /// line is skipped.
+    fn import_csv(&self, reader: &mut dyn BufRead) -> io::Result<()>
+    where
+        N: FromStr + Clone + Eq + Hash,
+    {
+        let mut batch: Vec<(N, Vec<N>)> = vec![];
+        let mut index = HashMap::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || (i == 0 && line == "parent,child") {
+                continue;
+            }
+            let parse = |field: Option<&str>| {
+                field
+                    .and_then(|f| f.trim().parse::<N>().ok())
+                    .ok_or_else(|| {
+                        let msg = format!("line {}: expected parent,child", i + 1);
+                        io::Error::new(io::ErrorKind::InvalidData, msg)
+                    })
+            };
+            let mut fields = line.splitn(2, ',');
+            let parent = parse(fields.next())?;
+            let child = parse(fields.next())?;
+            let slot = *index.entry(child).or_insert_with_key(|child| {
+                batch.push((child.clone(), vec![]));
+                batch.len() - 1
+            });
+            batch[slot].1.push(parent);
+        }
+        self.upsert_batch(batch);
+        Ok(())
+    }
+    /// The upserted nodes with their dependencies, type and metadata as
+    /// pretty-printed JSON, sorted so that equal graphs serialize
+    /// identically.
+    #[cfg(feature = "json")]
+    fn to_json(&self) -> String
+    where
+        N: Serialize + Ord + Clone,
+    {
+        let mut names = self.nodes();
+        names.sort();
+        let nodes = names
+            .into_iter()
+            .filter(|name| self.exists(name.clone()))
+            .map(|name| {
+                let mut dependencies = self.dependencies(name.clone());
+                dependencies.sort();
+                NodeDocument {
+                    dependencies,
+                    node_type: self.node_type(name.clone()),
+                    metadata: self
+                        .get_metadata(name.clone())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .collect(),
+                    name,
+                }
+            })
+            .collect();
+        serde_json::to_string_pretty(&GraphDocument { nodes }).unwrap()
+    }
+    /// Loads the nodes of `to_json` output, replacing the dependencies, type
+    /// and metadata of each one it lists.
+    #[cfg(feature = "json")]
+    fn from_json(&self, json: &str) -> serde_json::Result<()>
+    where
+        N: DeserializeOwned + Clone,
+    {
+        let document: GraphDocument<N> = serde_json::from_str(json)?;
+        let batch = document
+            .nodes
+            .iter()
+            .map(|node| (node.name.clone(), node.dependencies.clone()))
+            .collect();
+        self.upsert_batch(batch);
+        for node in document.nodes {
+            self.set_node_type(node.name.clone(), node.node_type);
+            self.set_metadata(node.name, node.metadata.into_iter().collect());
+        }
+        Ok(())
+    }
+    /// Loads the models, seeds, snapshots and sources of a dbt
+    /// `manifest.json` by `unique_id`, as one batch. Each node is typed by
+    /// its resource type and keeps its materialization, tags, database,
+    /// schema and package as metadata.
+    #[cfg(feature = "dbt")]
+    fn import_dbt_manifest(&self, path: &Path) -> io::Result<()>
+    where
+        N: From<String> + Clone,
+    {
+        let nodes = dbt::read_manifest(path)?;
+        let batch = nodes
+            .iter()
+            .map(|(id, dependencies, _, _)| {
+                let dependencies = dependencies.iter().cloned().map(N::from).collect();
+                (N::from(id.clone()), dependencies)
+            })
+            .collect();
+        self.upsert_batch(batch);
+        for (id, _, node_type, metadata) in nodes {
+            let name = N::from(id);
+            self.set_node_type(name.clone(), Some(node_type));
+            self.set_metadata(name, metadata);
+        }
+        Ok(())
+    }
+    /// Writes every edge as a `parent,child` line after a header line, the
+    /// counterpart of `import_csv`.
+    fn export_csv(&self, writer: &mut dyn Write) -> io::Result<()>
+    where
+        N: fmt::Display,
+    {
+        let mut edges: Vec<(String, String)> = self
+            .edges()
+            .into_iter()
+            .map(|(name, dependency)| (dependency.to_string(), name.to_string()))
+            .collect();
+        edges.sort();
+        writeln!(writer, "parent,child")?;
+        for (parent, child) in edges {
+            writeln!(writer, "{},{}", parent, child)?;
+        }
+        Ok(())
+    }
+}
+
+fn sorted<N: Ord>(result: HashMap<N, Vec<N>>) -> BTreeMap<N, Vec<N>> {
+    result
+        .into_iter()
+        .map(|(k, mut vs)| {
+            vs.sort();
+            (k, vs)
+        })
+        .collect()
+}
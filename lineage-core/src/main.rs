@@ -1,8 +1,8 @@
-pub mod differential;
-pub mod lineage;
+use lineage_core::differential;
+use lineage_core::lineage::Name;
 
 fn main() {
-    let lineage = differential::new();
+    let lineage = differential::new::<Name>();
     lineage.upsert(1, vec![2, 3]);
     lineage.upsert(2, vec![4, 5]);
     lineage.upsert(0, vec![1, 3]);
@@ -0,0 +1,285 @@
+//! The request/response protocol of `proto/lineage.proto`, for clients
+//! talking to the engine over the network. Kept in sync with the schema by
+//! hand, field tags must match.
+
+use std::collections::HashMap;
+
+use prost::{DecodeError, Message};
+
+use crate::lineage::Graph;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Empty {}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Name {
+    #[prost(string, tag = "1")]
+    pub name: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct NameK {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(uint64, tag = "2")]
+    pub k: u64,
+}
+
+/// A `(name, dependency)` edge.
+#[derive(Clone, PartialEq, Message)]
+pub struct Edge {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub dependency: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Upsert {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, repeated, tag = "2")]
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct UpsertBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub upserts: Vec<Upsert>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SetMetadata {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(map = "string, string", tag = "2")]
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Request {
+    #[prost(
+        oneof = "request::Kind",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21"
+    )]
+    pub kind: Option<request::Kind>,
+}
+
+pub mod request {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Kind {
+        #[prost(message, tag = "1")]
+        Exists(super::Name),
+        #[prost(message, tag = "2")]
+        Nodes(super::Empty),
+        #[prost(message, tag = "3")]
+        Edges(super::Empty),
+        #[prost(message, tag = "4")]
+        Dependencies(super::Name),
+        #[prost(message, tag = "5")]
+        Dependents(super::Name),
+        #[prost(message, tag = "6")]
+        DependenciesCascade(super::Name),
+        #[prost(message, tag = "7")]
+        DependentsCascade(super::Name),
+        #[prost(message, tag = "8")]
+        DependenciesK(super::NameK),
+        #[prost(message, tag = "9")]
+        DependentsK(super::NameK),
+        #[prost(message, tag = "10")]
+        Upsert(super::Upsert),
+        #[prost(message, tag = "11")]
+        Delete(super::Name),
+        #[prost(message, tag = "12")]
+        AddDependency(super::Edge),
+        #[prost(message, tag = "13")]
+        RemoveDependency(super::Edge),
+        #[prost(message, tag = "14")]
+        GetMetadata(super::Name),
+        #[prost(message, tag = "15")]
+        SetMetadata(super::SetMetadata),
+        #[prost(message, tag = "16")]
+        Roots(super::Empty),
+        #[prost(message, tag = "17")]
+        Leaves(super::Empty),
+        #[prost(message, tag = "18")]
+        Orphans(super::Empty),
+        #[prost(message, tag = "19")]
+        Impact(super::Name),
+        #[prost(message, tag = "20")]
+        Neighborhood(super::NameK),
+        #[prost(message, tag = "21")]
+        UpsertBatch(super::UpsertBatch),
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct NameList {
+    #[prost(string, repeated, tag = "1")]
+    pub names: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct EdgeList {
+    #[prost(message, repeated, tag = "1")]
+    pub edges: Vec<Edge>,
+}
+
+/// Every node of a cascade with its own dependencies or dependents.
+#[derive(Clone, PartialEq, Message)]
+pub struct Cascade {
+    #[prost(map = "string, message", tag = "1")]
+    pub entries: HashMap<String, NameList>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Metadata {
+    #[prost(bool, tag = "1")]
+    pub found: bool,
+    #[prost(map = "string, string", tag = "2")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// Both cascades of a node, read at the same time.
+#[derive(Clone, PartialEq, Message)]
+pub struct Impact {
+    #[prost(message, optional, tag = "1")]
+    pub upstream: Option<Cascade>,
+    #[prost(message, optional, tag = "2")]
+    pub downstream: Option<Cascade>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Response {
+    #[prost(oneof = "response::Kind", tags = "1, 2, 3, 4, 5, 6, 7")]
+    pub kind: Option<response::Kind>,
+}
+
+pub mod response {
+    #[derive(Clone, PartialEq, prost::Oneof)]
+    pub enum Kind {
+        #[prost(message, tag = "1")]
+        Done(super::Empty),
+        #[prost(bool, tag = "2")]
+        Exists(bool),
+        #[prost(message, tag = "3")]
+        Names(super::NameList),
+        #[prost(message, tag = "4")]
+        Edges(super::EdgeList),
+        #[prost(message, tag = "5")]
+        Cascade(super::Cascade),
+        #[prost(message, tag = "6")]
+        Metadata(super::Metadata),
+        #[prost(message, tag = "7")]
+        Impact(super::Impact),
+    }
+}
+
+/// One edge insert (`diff` 1) or retraction (`diff` -1) at `time`.
+#[derive(Clone, PartialEq, Message)]
+pub struct EdgeChange {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub dependency: String,
+    #[prost(uint64, tag = "3")]
+    pub time: u64,
+    #[prost(sint64, tag = "4")]
+    pub diff: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ChangeBatch {
+    #[prost(message, repeated, tag = "1")]
+    pub changes: Vec<EdgeChange>,
+}
+
+/// Answers `request` from `lineage`. A request without a kind, e.g. from a
+/// newer client, is answered with `Done`.
+pub fn handle(lineage: &dyn Graph<String>, request: Request) -> Response {
+    use request::Kind as Req;
+    use response::Kind as Res;
+
+    let names = |names| Res::Names(NameList { names });
+    let entries = |cascade: HashMap<String, Vec<String>>| {
+        let entries = cascade
+            .into_iter()
+            .map(|(k, names)| (k, NameList { names }))
+            .collect();
+        Cascade { entries }
+    };
+    let cascade = |cascade| Res::Cascade(entries(cascade));
+    let impact = |impact: crate::lineage::Impact<String>| {
+        Res::Impact(Impact {
+            upstream: Some(entries(impact.upstream)),
+            downstream: Some(entries(impact.downstream)),
+        })
+    };
+    let kind = match request.kind {
+        Some(Req::Exists(r)) => Res::Exists(lineage.exists(r.name)),
+        Some(Req::Nodes(_)) => names(lineage.nodes()),
+        Some(Req::Roots(_)) => names(lineage.roots()),
+        Some(Req::Leaves(_)) => names(lineage.leaves()),
+        Some(Req::Orphans(_)) => names(lineage.orphans()),
+        Some(Req::Edges(_)) => {
+            let edges = lineage
+                .edges()
+                .into_iter()
+                .map(|(name, dependency)| Edge { name, dependency })
+                .collect();
+            Res::Edges(EdgeList { edges })
+        }
+        Some(Req::Dependencies(r)) => names(lineage.dependencies(r.name)),
+        Some(Req::Dependents(r)) => names(lineage.dependents(r.name)),
+        Some(Req::DependenciesCascade(r)) => cascade(lineage.dependencies_cascade(r.name)),
+        Some(Req::DependentsCascade(r)) => cascade(lineage.dependents_cascade(r.name)),
+        Some(Req::DependenciesK(r)) => cascade(lineage.dependencies_k(r.name, r.k as usize)),
+        Some(Req::DependentsK(r)) => cascade(lineage.dependents_k(r.name, r.k as usize)),
+        Some(Req::Impact(r)) => impact(lineage.impact(r.name)),
+        Some(Req::Neighborhood(r)) => impact(lineage.neighborhood(r.name, r.k as usize)),
+        Some(Req::GetMetadata(r)) => {
+            let metadata = lineage.get_metadata(r.name);
+            Res::Metadata(Metadata {
+                found: metadata.is_some(),
+                metadata: metadata.unwrap_or_default(),
+            })
+        }
+        Some(Req::Upsert(r)) => {
+            lineage.upsert(r.name, r.dependencies);
+            Res::Done(Empty {})
+        }
+        Some(Req::UpsertBatch(r)) => {
+            let batch = r
+                .upserts
+                .into_iter()
+                .map(|u| (u.name, u.dependencies))
+                .collect();
+            lineage.upsert_batch(batch);
+            Res::Done(Empty {})
+        }
+        Some(Req::Delete(r)) => {
+            lineage.delete(r.name);
+            Res::Done(Empty {})
+        }
+        Some(Req::AddDependency(r)) => {
+            lineage.add_dependency(r.name, r.dependency);
+            Res::Done(Empty {})
+        }
+        Some(Req::RemoveDependency(r)) => {
+            lineage.remove_dependency(r.name, r.dependency);
+            Res::Done(Empty {})
+        }
+        Some(Req::SetMetadata(r)) => {
+            lineage.set_metadata(r.name, r.metadata);
+            Res::Done(Empty {})
+        }
+        None => Res::Done(Empty {}),
+    };
+    Response { kind: Some(kind) }
+}
+
+/// `handle` on the wire: decodes a `Request`, encodes the `Response`.
+pub fn handle_bytes(lineage: &dyn Graph<String>, bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let request = Request::decode(bytes)?;
+    Ok(handle(lineage, request).encode_to_vec())
+}
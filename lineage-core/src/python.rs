@@ -0,0 +1,85 @@
+//! The `differential_lineage` Python module, names being strings:
+//!
+//! ```python
+//! from differential_lineage import Lineage
+//! lineage = Lineage()
+//! lineage.upsert("report", ["orders", "customers"])
+//! lineage.dependencies_cascade("report")
+//! ```
+//!
+//! Every call releases the GIL while it waits for the worker thread.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use pyo3::prelude::*;
+
+use crate::differential;
+use crate::lineage::{Direction, Lineage};
+
+#[pyclass(name = "Lineage")]
+struct PyLineage {
+    inner: Arc<dyn Lineage<String>>,
+}
+
+#[pymethods]
+impl PyLineage {
+    #[new]
+    fn new() -> Self {
+        PyLineage {
+            inner: differential::new(),
+        }
+    }
+
+    fn upsert(&self, py: Python, name: String, dependencies: Vec<String>) {
+        py.allow_threads(|| self.inner.upsert(name, dependencies))
+    }
+
+    fn delete(&self, py: Python, name: String) {
+        py.allow_threads(|| self.inner.delete(name))
+    }
+
+    fn dependencies(&self, py: Python, name: String) -> Vec<String> {
+        py.allow_threads(|| self.inner.dependencies(name))
+    }
+
+    fn dependents(&self, py: Python, name: String) -> Vec<String> {
+        py.allow_threads(|| self.inner.dependents(name))
+    }
+
+    fn dependencies_cascade(&self, py: Python, name: String) -> HashMap<String, Vec<String>> {
+        py.allow_threads(|| self.inner.dependencies_cascade(name))
+    }
+
+    fn dependents_cascade(&self, py: Python, name: String) -> HashMap<String, Vec<String>> {
+        py.allow_threads(|| self.inner.dependents_cascade(name))
+    }
+
+    /// Graphviz source of the whole graph or of the cascade of `root`,
+    /// towards the dependencies unless `upstream` is false.
+    #[pyo3(signature = (root=None, upstream=true))]
+    fn export_dot(&self, py: Python, root: Option<String>, upstream: bool) -> String {
+        let direction = if upstream {
+            Direction::Upstream
+        } else {
+            Direction::Downstream
+        };
+        py.allow_threads(|| self.inner.export_dot(root, direction))
+    }
+
+    fn export_graphml(&self, py: Python) -> String {
+        py.allow_threads(|| self.inner.export_graphml())
+    }
+
+    fn export_csv(&self, py: Python) -> PyResult<String> {
+        let mut csv = vec![];
+        py.allow_threads(|| self.inner.export_csv(&mut csv))?;
+        Ok(String::from_utf8(csv).unwrap())
+    }
+}
+
+#[pymodule]
+fn differential_lineage(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyLineage>()?;
+    Ok(())
+}
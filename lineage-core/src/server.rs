@@ -0,0 +1,138 @@
+//! gRPC front end of the `proto` protocol, so that other services can
+//! query lineage without linking the crate.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::Server;
+use tonic::Status;
+
+use crate::lineage::Lineage;
+use crate::proto::{self, ChangeBatch, EdgeChange, Empty, Name, Request, Response};
+
+mod pb {
+    tonic::include_proto!("lineage");
+}
+
+use pb::lineage_server::{self, LineageServer};
+
+/// How many stream items may wait for a slow client before the feeding
+/// thread blocks.
+const STREAM_BUFFER: usize = 16;
+
+pub struct LineageService {
+    lineage: Arc<dyn Lineage<String>>,
+}
+
+impl LineageService {
+    pub fn new(lineage: Arc<dyn Lineage<String>>) -> Self {
+        LineageService { lineage }
+    }
+}
+
+#[tonic::async_trait]
+impl lineage_server::Lineage for LineageService {
+    async fn call(
+        &self,
+        request: tonic::Request<Request>,
+    ) -> Result<tonic::Response<Response>, Status> {
+        let lineage = self.lineage.clone();
+        let request = request.into_inner();
+        // every call blocks on the worker thread
+        let response = tokio::task::spawn_blocking(move || proto::handle(&*lineage, request))
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(tonic::Response::new(response))
+    }
+
+    type SubscribeDependentsCascadeStream = ReceiverStream<Result<ChangeBatch, Status>>;
+
+    async fn subscribe_dependents_cascade(
+        &self,
+        request: tonic::Request<Name>,
+    ) -> Result<tonic::Response<Self::SubscribeDependentsCascadeStream>, Status> {
+        let batches = self
+            .lineage
+            .subscribe_dependents_cascade(request.into_inner().name);
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        feed(batches, tx, |batch| {
+            let changes = batch
+                .into_iter()
+                .map(|((name, dependency), time, diff)| EdgeChange {
+                    name,
+                    dependency,
+                    time,
+                    diff: diff as i64,
+                })
+                .collect();
+            ChangeBatch { changes }
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+
+    type ChangesStream = ReceiverStream<Result<EdgeChange, Status>>;
+
+    async fn changes(
+        &self,
+        _: tonic::Request<Empty>,
+    ) -> Result<tonic::Response<Self::ChangesStream>, Status> {
+        let changes = self.lineage.changes();
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        feed(changes, tx, |(name, dependency, diff, time)| EdgeChange {
+            name,
+            dependency,
+            time,
+            diff: diff as i64,
+        });
+        Ok(tonic::Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Sends every update, converted by `convert`, to the stream of `tx` from a
+/// blocking thread until either side hangs up. A client going away ends the
+/// thread, and with it the subscription, even while nothing changes.
+fn feed<T, U>(
+    updates: crossbeam::channel::Receiver<T>,
+    tx: mpsc::Sender<Result<U, Status>>,
+    convert: impl Fn(T) -> U + Send + 'static,
+) where
+    T: Send + 'static,
+    U: Send + 'static,
+{
+    let (open, closed) = crossbeam::channel::bounded::<()>(0);
+    let (done, finished) = oneshot::channel::<()>();
+    let watched = tx.clone();
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = watched.closed() => {}
+            _ = finished => {}
+        }
+        drop(open);
+    });
+    tokio::task::spawn_blocking(move || {
+        // lets the task above go once the updates end first
+        let _done = done;
+        loop {
+            crossbeam::channel::select! {
+                recv(updates) -> update => match update {
+                    Ok(update) if tx.blocking_send(Ok(convert(update))).is_ok() => {}
+                    _ => break,
+                },
+                recv(closed) -> _ => break,
+            }
+        }
+    });
+}
+
+/// Serves `lineage` over gRPC on `addr` until the server fails.
+pub async fn serve(
+    lineage: Arc<dyn Lineage<String>>,
+    addr: SocketAddr,
+) -> Result<(), tonic::transport::Error> {
+    Server::builder()
+        .add_service(LineageServer::new(LineageService::new(lineage)))
+        .serve(addr)
+        .await
+}
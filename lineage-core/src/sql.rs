@@ -0,0 +1,121 @@
+//! Table-level lineage extracted from SQL, e.g. `CREATE TABLE x AS SELECT
+//! ... FROM a JOIN b` becoming `upsert(x, [a, b])`.
+
+use sqlparser::ast::{Query, SetExpr, Statement, TableFactor, TableWithJoins};
+use sqlparser::dialect::GenericDialect;
+use sqlparser::parser::{Parser, ParserError};
+
+use crate::lineage::Lineage;
+
+/// The tables one statement reads and the table it writes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableLineage {
+    pub target: String,
+    pub reads: Vec<String>,
+    /// Whether the statement defines `target` from scratch, as `CREATE
+    /// TABLE ... AS` and `CREATE VIEW` do, rather than adding rows to it.
+    pub replaces: bool,
+}
+
+/// The lineage of every statement of `sql` that writes a table. Reads are
+/// taken from `FROM` and `JOIN` clauses, derived tables and common table
+/// expressions included, not from subqueries within expressions.
+pub fn table_lineage(sql: &str) -> Result<Vec<TableLineage>, ParserError> {
+    let statements = Parser::parse_sql(&GenericDialect {}, sql)?;
+    let lineage = statements
+        .iter()
+        .filter_map(|statement| {
+            let (target, query, replaces) = match statement {
+                Statement::CreateTable {
+                    name,
+                    query: Some(query),
+                    ..
+                } => (name, query, true),
+                Statement::CreateView { name, query, .. } => (name, query, true),
+                Statement::Insert {
+                    table_name, source, ..
+                } => (table_name, source, false),
+                _ => return None,
+            };
+            let mut reads = vec![];
+            query_reads(query, &mut vec![], &mut reads);
+            reads.sort();
+            reads.dedup();
+            Some(TableLineage {
+                target: target.to_string(),
+                reads,
+                replaces,
+            })
+        })
+        .collect();
+    Ok(lineage)
+}
+
+/// Applies the lineage of `sql` statement by statement: defining a table
+/// replaces its dependencies, inserting into one adds to them.
+pub fn upsert_sql<N: From<String>>(lineage: &dyn Lineage<N>, sql: &str) -> Result<(), ParserError> {
+    for table in table_lineage(sql)? {
+        if table.replaces {
+            let reads = table.reads.into_iter().map(N::from).collect();
+            lineage.upsert(N::from(table.target), reads);
+        } else {
+            for read in table.reads {
+                lineage.add_dependency(N::from(table.target.clone()), N::from(read));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Adds the tables read by `query` to `reads`, `ctes` being the common
+/// table expressions in scope, which are not tables.
+fn query_reads(query: &Query, ctes: &mut Vec<String>, reads: &mut Vec<String>) {
+    let scope = ctes.len();
+    if let Some(with) = &query.with {
+        for cte in &with.cte_tables {
+            query_reads(&cte.query, ctes, reads);
+            ctes.push(cte.alias.name.value.clone());
+        }
+    }
+    set_expr_reads(&query.body, ctes, reads);
+    ctes.truncate(scope);
+}
+
+fn set_expr_reads(body: &SetExpr, ctes: &mut Vec<String>, reads: &mut Vec<String>) {
+    match body {
+        SetExpr::Select(select) => {
+            for table in &select.from {
+                table_reads(table, ctes, reads);
+            }
+        }
+        SetExpr::Query(query) => query_reads(query, ctes, reads),
+        SetExpr::SetOperation { left, right, .. } => {
+            set_expr_reads(left, ctes, reads);
+            set_expr_reads(right, ctes, reads);
+        }
+        _ => {}
+    }
+}
+
+fn table_reads(table: &TableWithJoins, ctes: &mut Vec<String>, reads: &mut Vec<String>) {
+    factor_reads(&table.relation, ctes, reads);
+    for join in &table.joins {
+        factor_reads(&join.relation, ctes, reads);
+    }
+}
+
+fn factor_reads(factor: &TableFactor, ctes: &mut Vec<String>, reads: &mut Vec<String>) {
+    match factor {
+        TableFactor::Table { name, .. } => {
+            let is_cte = name.0.len() == 1 && ctes.contains(&name.0[0].value);
+            if !is_cte {
+                reads.push(name.to_string());
+            }
+        }
+        TableFactor::Derived { subquery, .. } => query_reads(subquery, ctes, reads),
+        TableFactor::NestedJoin {
+            table_with_joins, ..
+        } => table_reads(table_with_joins, ctes, reads),
+        _ => {}
+    }
+}
@@ -0,0 +1,102 @@
+//! The cascade subscriptions of the front ends pushing changes, see
+//! `websocket` and `uds`: one standing query per cascade, shared by all of
+//! its subscribers and released after the last of them.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crossbeam::channel::Receiver;
+use serde::Serialize;
+
+use crate::lineage::{ChangeBatch, Direction, Lineage, QueryHandle, QuerySpec};
+
+/// One edge insert (`diff` 1) or retraction (`diff` -1) at `time`, as the
+/// front ends send it.
+#[derive(Serialize)]
+pub(crate) struct Change {
+    name: String,
+    dependency: String,
+    time: u64,
+    diff: isize,
+}
+
+pub(crate) fn changes(batch: ChangeBatch<String>) -> Vec<Change> {
+    batch
+        .into_iter()
+        .map(|((name, dependency), time, diff)| Change {
+            name,
+            dependency,
+            time,
+            diff,
+        })
+        .collect()
+}
+
+/// The direction named by a request, downstream, i.e. the dependents, when
+/// not given. `None` for any other name.
+pub(crate) fn direction(name: Option<&str>) -> Option<Direction> {
+    match name {
+        None | Some("downstream") => Some(Direction::Downstream),
+        Some("upstream") => Some(Direction::Upstream),
+        Some(_) => None,
+    }
+}
+
+pub(crate) struct Subscriptions {
+    lineage: Arc<dyn Lineage<String>>,
+    /// One standing query per cascade with its number of subscribers.
+    queries: Mutex<HashMap<(String, Direction), (QueryHandle, usize)>>,
+}
+
+impl Subscriptions {
+    pub(crate) fn new(lineage: Arc<dyn Lineage<String>>) -> Arc<Self> {
+        Arc::new(Subscriptions {
+            lineage,
+            queries: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The current edges of the cascade of `id` as inserts, then every later
+    /// change, registering its query for the first subscriber.
+    pub(crate) fn subscribe(self: &Arc<Self>, id: String, direction: Direction) -> Subscription {
+        let key = (id.clone(), direction);
+        // held until subscribed, so that the query isn't released meanwhile
+        let mut queries = self.queries.lock().unwrap();
+        let (handle, subscribers) = queries.entry(key.clone()).or_insert_with(|| {
+            let spec = match direction {
+                Direction::Upstream => QuerySpec::DependenciesCascade(id),
+                Direction::Downstream => QuerySpec::DependentsCascade(id),
+            };
+            (self.lineage.register_query(spec), 0)
+        });
+        *subscribers += 1;
+        let updates = self.lineage.subscribe_query(*handle);
+        Subscription {
+            subscriptions: self.clone(),
+            key,
+            updates,
+        }
+    }
+}
+
+/// Unsubscribes when dropped.
+pub(crate) struct Subscription {
+    subscriptions: Arc<Subscriptions>,
+    key: (String, Direction),
+    pub(crate) updates: Receiver<ChangeBatch<String>>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut queries = self.subscriptions.queries.lock().unwrap();
+        let Some((handle, subscribers)) = queries.get_mut(&self.key) else {
+            return;
+        };
+        *subscribers -= 1;
+        if *subscribers == 0 {
+            let handle = *handle;
+            queries.remove(&self.key);
+            self.subscriptions.lineage.release_query(handle);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Tracing of the requests to the differential backend.
+//!
+//! Every call is handled in a `handle` span whose parent is the span current
+//! when the call was made, and every query in a span named after it. Calls
+//! made within `request_span` can then be found by their request id.
+
+use tracing::Span;
+
+/// A span to make calls in, carrying the request id of the caller.
+pub fn request_span(request_id: &str) -> Span {
+    tracing::info_span!("request", request_id)
+}
+
+/// Exports the spans to the OTLP collector at `endpoint`, filtered by
+/// `RUST_LOG`. Must be called from within a tokio runtime, which sends the
+/// spans in batches.
+#[cfg(feature = "otlp")]
+pub fn init_otlp(endpoint: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(tracing_opentelemetry::layer().with_tracer(tracer))
+        .try_init()?;
+    Ok(())
+}
+
+/// Sends the spans not exported yet.
+#[cfg(feature = "otlp")]
+pub fn shutdown_otlp() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
@@ -0,0 +1,166 @@
+//! Unix domain socket front end speaking newline-delimited JSON, for
+//! sidecars on the same host. Every line is one request, tagged by its
+//! `verb`, and gets one line back:
+//!
+//! - `{"verb": "DEPENDENCIES", "id": "a", "depth": 2}` and `"DEPENDENTS"`
+//!   answer `{"nodes": {...}}` with the cascade, limited to `depth` hops
+//!   when given
+//! - `{"verb": "UPSERT", "id": "a", "dependencies": ["b"]}` and
+//!   `{"verb": "DELETE", "id": "a"}` answer `{"ok": true}`
+//! - `{"verb": "SUBSCRIBE", "id": "a", "direction": "upstream"}` turns the
+//!   connection into a change feed of the cascade, one line per batch of
+//!   `{"name", "dependency", "time", "diff"}` objects, starting with the
+//!   current edges as inserts. The direction defaults to downstream.
+//!
+//! A line that can't be parsed answers `{"error": "..."}`.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::lineage::{Direction, Lineage};
+use crate::subscriptions::{self, Subscriptions};
+
+#[derive(Deserialize)]
+#[serde(tag = "verb", rename_all = "SCREAMING_SNAKE_CASE")]
+enum Request {
+    Dependencies {
+        id: String,
+        depth: Option<usize>,
+    },
+    Dependents {
+        id: String,
+        depth: Option<usize>,
+    },
+    Upsert {
+        id: String,
+        dependencies: Vec<String>,
+    },
+    Delete {
+        id: String,
+    },
+    Subscribe {
+        id: String,
+        direction: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Response {
+    Nodes(HashMap<String, Vec<String>>),
+    Ok(bool),
+    Error(String),
+}
+
+struct Server {
+    lineage: Arc<dyn Lineage<String>>,
+    subscriptions: Arc<Subscriptions>,
+}
+
+/// Serves the protocol of the module documentation on a socket created at
+/// `path`, one thread per connection, until accepting fails.
+pub fn serve(lineage: Arc<dyn Lineage<String>>, path: impl AsRef<Path>) -> io::Result<()> {
+    let listener = UnixListener::bind(path)?;
+    let server = Arc::new(Server {
+        subscriptions: Subscriptions::new(lineage.clone()),
+        lineage,
+    });
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = server.clone();
+        std::thread::spawn(move || {
+            // the client hanging up ends the connection either way
+            let _ = server.connection(stream);
+        });
+    }
+    Ok(())
+}
+
+impl Server {
+    fn connection(&self, stream: UnixStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str(&line) {
+                Ok(Request::Subscribe { id, direction }) => {
+                    let Some(known) = subscriptions::direction(direction.as_deref()) else {
+                        let name = direction.unwrap_or_default();
+                        let msg = format!("unknown direction {:?}", name);
+                        reply(&mut writer, &Response::Error(msg))?;
+                        continue;
+                    };
+                    return self.feed(&mut writer, id, known);
+                }
+                Ok(request) => self.answer(request),
+                Err(err) => Response::Error(err.to_string()),
+            };
+            reply(&mut writer, &response)?;
+        }
+        Ok(())
+    }
+
+    fn answer(&self, request: Request) -> Response {
+        match request {
+            Request::Dependencies { id, depth } => Response::Nodes(match depth {
+                Some(k) => self.lineage.dependencies_k(id, k),
+                None => self.lineage.dependencies_cascade(id),
+            }),
+            Request::Dependents { id, depth } => Response::Nodes(match depth {
+                Some(k) => self.lineage.dependents_k(id, k),
+                None => self.lineage.dependents_cascade(id),
+            }),
+            Request::Upsert { id, dependencies } => {
+                self.lineage.upsert(id, dependencies);
+                Response::Ok(true)
+            }
+            Request::Delete { id } => {
+                self.lineage.delete(id);
+                Response::Ok(true)
+            }
+            Request::Subscribe { .. } => unreachable!("subscriptions take over the connection"),
+        }
+    }
+
+    /// Writes every change of the cascade until the client hangs up.
+    fn feed(&self, writer: &mut UnixStream, id: String, direction: Direction) -> io::Result<()> {
+        // the client hanging up ends the feed even while the cascade
+        // doesn't change, anything it still sends is ignored
+        let (open, closed) = crossbeam::channel::bounded::<()>(0);
+        let mut reader = writer.try_clone()?;
+        std::thread::spawn(move || {
+            let _ = io::copy(&mut reader, &mut io::sink());
+            drop(open);
+        });
+        // unsubscribes once the feed ends
+        let subscription = self.subscriptions.subscribe(id, direction);
+        let fed = loop {
+            crossbeam::channel::select! {
+                recv(subscription.updates) -> batch => {
+                    let Ok(batch) = batch else { break Ok(()) };
+                    if let Err(err) = reply(writer, &subscriptions::changes(batch)) {
+                        break Err(err);
+                    }
+                }
+                recv(closed) -> _ => break Ok(()),
+            }
+        };
+        // ends the thread reading from the client too
+        let _ = writer.shutdown(Shutdown::Both);
+        fed
+    }
+}
+
+fn reply(writer: &mut UnixStream, value: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line)
+}
@@ -0,0 +1,84 @@
+//! WebSocket endpoint pushing the changes of a cascade to the browser as
+//! they happen: `GET /nodes/{id}/subscribe?direction=upstream` upgrades to a
+//! socket receiving the current edges of the cascade as inserts, then every
+//! later change, each batch as one JSON text message of
+//! `{"name", "dependency", "time", "diff"}` objects. The direction defaults
+//! to downstream, i.e. the dependents.
+
+use std::sync::Arc;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+
+use crate::lineage::{Direction, Lineage};
+use crate::subscriptions::{self, Subscriptions};
+
+/// How many batches may wait for a slow browser before the feeding thread
+/// blocks.
+const BUFFER: usize = 16;
+
+#[derive(Deserialize)]
+struct Subscribe {
+    direction: Option<String>,
+}
+
+/// The subscription route of the module documentation, to be merged with
+/// `http::router`.
+pub fn router(lineage: Arc<dyn Lineage<String>>) -> Router {
+    let state = Subscriptions::new(lineage);
+    Router::new()
+        .route("/nodes/:id/subscribe", get(subscribe))
+        .with_state(state)
+}
+
+async fn subscribe(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<Subscriptions>>,
+    Path(id): Path<String>,
+    Query(query): Query<Subscribe>,
+) -> Response {
+    let Some(direction) = subscriptions::direction(query.direction.as_deref()) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    ws.on_upgrade(move |socket| push(socket, state, id, direction))
+}
+
+async fn push(mut socket: WebSocket, state: Arc<Subscriptions>, id: String, direction: Direction) {
+    let (tx, mut rx) = mpsc::channel(BUFFER);
+    // dropped once the socket closes, ending the feeding thread even while
+    // the cascade doesn't change
+    let (_open, closed) = crossbeam::channel::bounded::<()>(0);
+    tokio::task::spawn_blocking(move || {
+        // unsubscribes once the socket closes
+        let subscription = state.subscribe(id, direction);
+        loop {
+            crossbeam::channel::select! {
+                recv(subscription.updates) -> batch => match batch {
+                    Ok(batch) if tx.blocking_send(batch).is_ok() => {}
+                    _ => break,
+                },
+                recv(closed) -> _ => break,
+            }
+        }
+    });
+    loop {
+        let batch = tokio::select! {
+            batch = rx.recv() => batch,
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        let Some(batch) = batch else { break };
+        let text = serde_json::to_string(&subscriptions::changes(batch)).unwrap();
+        if socket.send(Message::Text(text)).await.is_err() {
+            break;
+        }
+    }
+}